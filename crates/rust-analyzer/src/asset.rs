@@ -2,12 +2,46 @@
 ///!
 ///! This module provides data structures to represent a collection of files,
 ///! their ASTs, and associated diagnostics, similar to the Go implementation.
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
-use std::collections::HashMap;
-use std::io::{self, Read, Write, Seek, SeekFrom};
+use anyhow::Context;
+use rustc_hash::{FxHashMap, FxHashSet};
+use sha2::{Digest, Sha256};
+use syntax::{Edition, SyntaxKind};
 
 const MAGIC: u8 = 0xde;
-const ASSET_ENCODING_VERSION: u32 = 1;
+/// The encoding version `Project::encode` writes.
+pub const CURRENT_ASSET_VERSION: u32 = 13;
+/// The encoding versions `Project::decode` understands.
+const SUPPORTED_ASSET_VERSIONS: &[u32] =
+    &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, CURRENT_ASSET_VERSION];
+/// Written at the end of each segment since version 9, after the string table and the file index,
+/// so that a truncated table is detected instead of silently yielding fewer strings.
+const END_MARKER: [u8; 2] = [MAGIC, MAGIC];
+/// The first byte of a `MultiProject` asset, distinct from `MAGIC` so it is not mistaken for a
+/// single project.
+const MULTI_MAGIC: u8 = 0xdf;
+/// The layout version of the `MultiProject` container, independent of the asset version of the
+/// projects inside it.
+const MULTI_PROJECT_VERSION: u32 = 1;
+
+/// Set in a file record's flags when each node record carries a subtree hash.
+const FILE_FLAG_SUBTREE_HASHES: u32 = 1 << 0;
+/// Set in a file record's flags when the string index of the file's shebang follows the flags.
+/// Only used since version 7.
+const FILE_FLAG_SHEBANG: u32 = 1 << 1;
+/// Set in a file record's flags when its node records store LEB128 deltas instead of absolute
+/// offsets, see `EncodeOptions::delta_offsets`. Only used since version 12.
+const FILE_FLAG_DELTA_OFFSETS: u32 = 1 << 2;
+/// Set in a file record's flags when `File::line_offsets` follow the metadata records. Only used
+/// since version 13.
+const FILE_FLAG_LINE_OFFSETS: u32 = 1 << 3;
+/// Set in an annotation record's flags when the string index of its context follows the flags.
+/// Annotation flags are only stored since version 10.
+const ANNOTATION_FLAG_CONTEXT: u8 = 1 << 0;
 
 /// Represents a range in a file (start and end offsets).
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,6 +59,195 @@ pub struct Node {
     pub range: Range,
     /// The type name of the node.
     pub node_type: String,
+    /// (optional) A hash of the node kind and the kinds and relative ranges of its descendants.
+    ///
+    /// Structurally identical subtrees get equal hashes regardless of where they are in the file.
+    pub subtree_hash: Option<u64>,
+}
+
+/// The severity of an annotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A problem, such as a parser error.
+    Error,
+    /// A potential problem, such as a lint.
+    Warning,
+    /// Purely informational, such as a marker for a construct of interest.
+    Note,
+}
+
+impl Severity {
+    fn to_u8(self) -> u8 {
+        match self {
+            Severity::Error => 0,
+            Severity::Warning => 1,
+            Severity::Note => 2,
+        }
+    }
+
+    fn from_u8(value: u8) -> anyhow::Result<Self> {
+        Ok(match value {
+            0 => Severity::Error,
+            1 => Severity::Warning,
+            2 => Severity::Note,
+            _ => anyhow::bail!("invalid severity {}", value),
+        })
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+}
+
+/// The declared visibility of an item.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    /// `pub`.
+    Public,
+    /// `pub(crate)`.
+    Crate,
+    /// `pub(super)`.
+    Super,
+    /// `pub(in path)`, with the path as written.
+    Restricted(String),
+    /// No visibility, or `pub(self)`.
+    Private,
+}
+
+impl Visibility {
+    pub(crate) fn to_metadata_value(&self) -> String {
+        match self {
+            Visibility::Public => "pub".to_owned(),
+            Visibility::Crate => "pub(crate)".to_owned(),
+            Visibility::Super => "pub(super)".to_owned(),
+            Visibility::Restricted(path) => format!("pub(in {path})"),
+            Visibility::Private => "private".to_owned(),
+        }
+    }
+
+    pub(crate) fn from_metadata_value(value: &str) -> Option<Self> {
+        Some(match value {
+            "pub" => Visibility::Public,
+            "pub(crate)" => Visibility::Crate,
+            "pub(super)" => Visibility::Super,
+            "private" => Visibility::Private,
+            _ => {
+                Visibility::Restricted(value.strip_prefix("pub(in ")?.strip_suffix(')')?.to_owned())
+            }
+        })
+    }
+}
+
+/// How a comment or whitespace token relates to the code around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriviaAssociation {
+    /// Belongs to the code after it, such as a comment on the line before an item.
+    Leading,
+    /// Belongs to the code before it on the same line, such as `x; // x`.
+    Trailing,
+    /// Separated from the code after it by a blank line, or at the end of the file.
+    Standalone,
+}
+
+impl TriviaAssociation {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            TriviaAssociation::Leading => "leading",
+            TriviaAssociation::Trailing => "trailing",
+            TriviaAssociation::Standalone => "standalone",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        Some(match value {
+            "leading" => TriviaAssociation::Leading,
+            "trailing" => TriviaAssociation::Trailing,
+            "standalone" => TriviaAssociation::Standalone,
+            _ => return None,
+        })
+    }
+}
+
+/// A test function found in a file, see `File::tests`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestItem {
+    /// The name of the function.
+    pub name: String,
+    /// The range of the function, including its attributes.
+    pub range: Range,
+}
+
+/// The parameters and return type of a function, see `File::signature`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnSignature {
+    /// The text of each parameter, including the `self` receiver, such as `&self` or `x: i32`.
+    pub params: Vec<String>,
+    /// The text of the return type, `None` if the function returns unit implicitly.
+    pub ret: Option<String>,
+}
+
+/// The trait and self type of an impl block, see `File::impl_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImplInfo {
+    /// The implemented trait as written, such as `Clone` or `!Send`. `None` for inherent impls.
+    pub trait_: Option<String>,
+    /// The self type as written, including generic arguments, such as `Foo<T>`.
+    pub target: String,
+}
+
+/// The kind of an item declared in a trait, see `TraitItem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TraitItemKind {
+    /// An associated function or method.
+    Fn,
+    /// An associated type.
+    Type,
+    /// An associated const.
+    Const,
+}
+
+/// An associated function, type or const declared in a trait, see `File::trait_items`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraitItem {
+    /// Whether the item is a function, type or const.
+    pub kind: TraitItemKind,
+    /// The declaration as written, without attributes, doc comments and the default, such as
+    /// `fn a(&self) -> i32`, `type Item: Clone` or `const N: usize`.
+    pub signature: String,
+    /// Whether the trait provides a default: a body, type or value.
+    pub has_default: bool,
+}
+
+impl TraitItem {
+    pub(crate) fn to_metadata_value(&self) -> String {
+        let kind = match self.kind {
+            TraitItemKind::Fn => "fn",
+            TraitItemKind::Type => "type",
+            TraitItemKind::Const => "const",
+        };
+        let default = if self.has_default { "default" } else { "required" };
+        format!("{kind} {default} {}", self.signature)
+    }
+
+    fn from_metadata_value(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(3, ' ');
+        let kind = match parts.next()? {
+            "fn" => TraitItemKind::Fn,
+            "type" => TraitItemKind::Type,
+            "const" => TraitItemKind::Const,
+            _ => return None,
+        };
+        let has_default = match parts.next()? {
+            "default" => true,
+            "required" => false,
+            _ => return None,
+        };
+        Some(TraitItem { kind, signature: parts.next()?.to_owned(), has_default })
+    }
 }
 
 /// Represents a message annotation (or a parser error) for a range/offset.
@@ -34,6 +257,80 @@ pub struct Annotation {
     pub range: Range,
     /// The annotation text.
     pub text: String,
+    /// The severity of the annotation. Assets older than version 6 only contain errors.
+    pub severity: Severity,
+    /// (optional) The source text of the annotated region, possibly truncated, so that the
+    /// annotation can be shown without the file's content. Only stored since version 10.
+    pub context: Option<String>,
+}
+
+/// Represents the decoded value of a string, byte string or char literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Literal {
+    /// The range of the literal token, including prefixes and quotes.
+    pub range: Range,
+    /// The kind name of the literal token, e.g. `STRING` or `CHAR`.
+    pub kind: String,
+    /// The value of the literal with escape sequences resolved.
+    pub value: String,
+}
+
+/// Metadata key for the name of a derive macro applied to an item.
+pub const METADATA_DERIVE: &str = "derive";
+/// Metadata key for the name of a generic parameter of an item, such as `'a`, `T` or `N`.
+pub const METADATA_GENERIC: &str = "generic";
+/// Metadata key for the path of a macro call, attached to the range of the call's arguments.
+pub const METADATA_MACRO_CALL: &str = "macro_call";
+/// Metadata key recording at the start of a file that a leading UTF-8 byte order mark was removed
+/// from its content. The value is empty.
+pub const METADATA_BOM_STRIPPED: &str = "bom_stripped";
+/// Metadata key for the `Visibility` of an item, attached to the item's range.
+pub const METADATA_VISIBILITY: &str = "visibility";
+/// Metadata key for the number of lines an item spans, attached to the item's range.
+pub const METADATA_LINE_SPAN: &str = "line_span";
+/// Metadata key for the name of a test function, attached to the function's range.
+pub const METADATA_TEST: &str = "test";
+/// Metadata key for the `TriviaAssociation` of a comment or whitespace token.
+pub const METADATA_TRIVIA: &str = "trivia";
+/// Metadata key for the text of a function parameter, attached to the function's range.
+pub const METADATA_PARAM: &str = "param";
+/// Metadata key for the text of a function's return type, attached to the function's range. The
+/// value is empty for functions without one, so every captured function has exactly one entry.
+pub const METADATA_RETURN: &str = "return";
+/// Metadata key for the self type of an impl block, attached to the impl's range.
+pub const METADATA_IMPL_TARGET: &str = "impl_target";
+/// Metadata key for the trait of a trait impl, attached to the impl's range.
+pub const METADATA_IMPL_TRAIT: &str = "impl_trait";
+/// Metadata key for a path imported by a `use` item, attached to the item's range.
+pub const METADATA_IMPORT: &str = "import";
+/// Metadata key for the inner doc comments at the top of a file, attached to the range from the
+/// first to the last of them.
+pub const METADATA_MODULE_DOC: &str = "module_doc";
+/// Metadata key for the number of nodes in the initializer expression of a `const` or `static`,
+/// attached to the item's range.
+pub const METADATA_INITIALIZER_NODES: &str = "initializer_nodes";
+/// Metadata key for a field of a struct, attached to the struct's range. The value is the name of
+/// the field, or its index for tuple structs, and the text of its type, separated by `:`.
+pub const METADATA_FIELD: &str = "field";
+/// Metadata key for the name of an enum variant, attached to the enum's range.
+pub const METADATA_VARIANT: &str = "variant";
+/// Metadata key for a `TraitItem` of a trait, attached to the trait's range.
+pub const METADATA_TRAIT_ITEM: &str = "trait_item";
+/// Metadata key for the pseudonym replacing the text of an identifier or literal token.
+pub const METADATA_TOKEN: &str = "token";
+
+/// Represents a piece of information extracted from the AST and attached to a range, usually the
+/// range of an item node.
+///
+/// Properties with several values are stored as one entry per value, all with the same key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// The range the entry is attached to.
+    pub range: Range,
+    /// The property the entry describes, e.g. `METADATA_DERIVE`.
+    pub key: String,
+    /// The value of the property.
+    pub value: String,
 }
 
 /// Represents a file which can be encoded as an asset.
@@ -45,10 +342,577 @@ pub struct File {
     pub path: String,
     /// The file content.
     pub content: String,
-    /// (optional) The AST of the file encoded as a flat list of nodes, in preorder.
+    /// (optional) The AST of the file encoded as a flat list of nodes, in preorder. Runs of
+    /// consecutive nodes with the same range are ordered by depth, then kind.
     pub tree: Vec<Node>,
     /// (optional) Any problems encountered by the compiler when processing this file.
     pub errors: Vec<Annotation>,
+    /// (optional) The decoded string and char literals of the file, in source order.
+    pub literals: Vec<Literal>,
+    /// (optional) Extra information extracted from the AST, in source order.
+    pub metadata: Vec<Metadata>,
+    /// The `#!` line the file starts with, if any, without the line break. It remains part of
+    /// `content`, so offsets are unaffected.
+    pub shebang: Option<String>,
+    /// The edition the file was parsed under. Assets from before version 8 do not record it and
+    /// decode as `Edition::CURRENT`.
+    pub edition: Edition,
+    /// (optional) The offset at which each line of `content` starts, see `File::line_range`.
+    /// Stored so that consumers can access lines without scanning the content first.
+    pub line_offsets: Vec<u32>,
+}
+
+impl Default for File {
+    fn default() -> Self {
+        File {
+            path: String::new(),
+            content: String::new(),
+            tree: Vec::new(),
+            errors: Vec::new(),
+            literals: Vec::new(),
+            metadata: Vec::new(),
+            shebang: None,
+            edition: Edition::CURRENT,
+            line_offsets: Vec::new(),
+        }
+    }
+}
+
+/// Node kinds treated as items by the item-level queries on `File`.
+const ITEM_KINDS: &[&str] = &[
+    "CONST",
+    "ENUM",
+    "FN",
+    "IMPL",
+    "MACRO_DEF",
+    "MACRO_RULES",
+    "MODULE",
+    "STATIC",
+    "STRUCT",
+    "TRAIT",
+    "TYPE_ALIAS",
+    "UNION",
+];
+
+/// Node kinds that form the body of an item, excluded from its signature.
+const ITEM_BODY_KINDS: &[&str] = &[
+    "ASSOC_ITEM_LIST",
+    "BLOCK_EXPR",
+    "ITEM_LIST",
+    "RECORD_FIELD_LIST",
+    "TOKEN_TREE",
+    "VARIANT_LIST",
+];
+
+impl File {
+    /// Returns the metadata values stored under `key` for `range`, in order.
+    pub fn metadata_values(&self, range: &Range, key: &str) -> Vec<&str> {
+        self.metadata
+            .iter()
+            .filter(|entry| entry.range == *range && entry.key == key)
+            .map(|entry| entry.value.as_str())
+            .collect()
+    }
+
+    /// Returns the names of the derive macros applied to `item`, as captured by the parser.
+    pub fn derives(&self, item: &Node) -> Vec<&str> {
+        self.metadata_values(&item.range, METADATA_DERIVE)
+    }
+
+    /// Returns the declared visibility of `item`, if the parser captured it.
+    ///
+    /// This is the visibility as written: trait items and the items of trait impls report
+    /// `Visibility::Private` even though they are as visible as their trait.
+    pub fn visibility(&self, item: &Node) -> Option<Visibility> {
+        self.metadata_values(&item.range, METADATA_VISIBILITY)
+            .first()
+            .and_then(|value| Visibility::from_metadata_value(value))
+    }
+
+    /// Returns the names of the lifetime, type and const parameters of `item` in declaration
+    /// order, as captured by the parser. Lifetimes include their leading `'`.
+    pub fn generics(&self, item: &Node) -> Vec<&str> {
+        self.metadata_values(&item.range, METADATA_GENERIC)
+    }
+
+    /// Returns the parameters and return type of the function `item`, if the parser captured them.
+    pub fn signature(&self, item: &Node) -> Option<FnSignature> {
+        let ret = *self.metadata_values(&item.range, METADATA_RETURN).first()?;
+        Some(FnSignature {
+            params: self
+                .metadata_values(&item.range, METADATA_PARAM)
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            ret: (!ret.is_empty()).then(|| ret.to_owned()),
+        })
+    }
+
+    /// Returns the trait and self type of the impl block `item`, if the parser captured them.
+    pub fn impl_info(&self, item: &Node) -> Option<ImplInfo> {
+        let target = *self.metadata_values(&item.range, METADATA_IMPL_TARGET).first()?;
+        Some(ImplInfo {
+            trait_: self
+                .metadata_values(&item.range, METADATA_IMPL_TRAIT)
+                .first()
+                .map(|trait_| (*trait_).to_owned()),
+            target: target.to_owned(),
+        })
+    }
+
+    /// Returns the name and type text of every field of the struct `item` in declaration order, as
+    /// captured by the parser. The fields of tuple structs are named by their index, unit structs
+    /// have none.
+    pub fn struct_fields(&self, item: &Node) -> Vec<(&str, &str)> {
+        self.metadata_values(&item.range, METADATA_FIELD)
+            .into_iter()
+            .filter_map(|field| field.split_once(':'))
+            .collect()
+    }
+
+    /// Returns the names of the variants of the enum `item` in declaration order, as captured by
+    /// the parser.
+    pub fn enum_variants(&self, item: &Node) -> Vec<&str> {
+        self.metadata_values(&item.range, METADATA_VARIANT)
+    }
+
+    /// Returns the associated functions, types and consts declared in the trait `item` in
+    /// declaration order, as captured by the parser.
+    pub fn trait_items(&self, item: &Node) -> Vec<TraitItem> {
+        self.metadata_values(&item.range, METADATA_TRAIT_ITEM)
+            .into_iter()
+            .filter_map(TraitItem::from_metadata_value)
+            .collect()
+    }
+
+    /// Returns the paths imported by the `use` items of the file, in source order, as captured by
+    /// the parser.
+    ///
+    /// Groups are flattened into one path each: `use a::{b, c::*, self as d};` yields `a::b`,
+    /// `a::c::*` and `a as d`.
+    pub fn imports(&self) -> Vec<&str> {
+        self.metadata
+            .iter()
+            .filter(|entry| entry.key == METADATA_IMPORT)
+            .map(|entry| entry.value.as_str())
+            .collect()
+    }
+
+    /// Returns the text of the `//!` and `/*! */` doc comments before the first item of the file,
+    /// if the parser captured any, with the comment markers removed.
+    pub fn module_doc(&self) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|entry| entry.key == METADATA_MODULE_DOC)
+            .map(|entry| entry.value.as_str())
+    }
+
+    /// Returns the number of nodes in the initializer expression of the `const` or `static` item
+    /// `item`, including the expression itself, if the parser captured it.
+    pub fn initializer_nodes(&self, item: &Node) -> Option<usize> {
+        self.metadata_values(&item.range, METADATA_INITIALIZER_NODES).first()?.parse().ok()
+    }
+
+    /// Returns the range and association of every comment and whitespace token, in source order,
+    /// as captured by the parser.
+    pub fn trivia(&self) -> Vec<(&Range, TriviaAssociation)> {
+        self.metadata
+            .iter()
+            .filter(|entry| entry.key == METADATA_TRIVIA)
+            .filter_map(|entry| Some((&entry.range, TriviaAssociation::from_str(&entry.value)?)))
+            .collect()
+    }
+
+    /// Returns the signature of every item in the file, in preorder, together with its range.
+    ///
+    /// The signature spans from the start of the item, including its attributes and doc comments,
+    /// up to but excluding its body (the block of a function, the field list of a struct, the item
+    /// list of an impl and so on). For items without a body, such as `struct S;`, it is the whole
+    /// item without the trailing semicolon. Surrounding whitespace is not included.
+    pub fn item_signatures(&self) -> Vec<(Range, String)> {
+        let parents = self.parents();
+        let mut signatures = Vec::new();
+        for (idx, node) in self.tree.iter().enumerate() {
+            if !ITEM_KINDS.contains(&node.node_type.as_str()) {
+                continue;
+            }
+            let body = (idx + 1..self.tree.len())
+                .take_while(|&child| self.tree[child].range.offset < node.range.end_offset)
+                .filter(|&child| parents[child] == Some(idx))
+                .map(|child| &self.tree[child])
+                .last()
+                .filter(|child| ITEM_BODY_KINDS.contains(&child.node_type.as_str()));
+            let end = body.map_or(node.range.end_offset, |body| body.range.offset);
+            let Some(text) = self.content.get(node.range.offset..end) else { continue };
+            let text = text.trim_end();
+            let text = if body.is_none() { text.trim_end_matches(';').trim_end() } else { text };
+            let leading = text.len() - text.trim_start().len();
+            let range = Range {
+                offset: node.range.offset + leading,
+                end_offset: node.range.offset + text.len(),
+            };
+            signatures.push((range, text.trim_start().to_owned()));
+        }
+        signatures
+    }
+
+    /// Returns the share of `ERROR` nodes in the tree, 0.0 for an empty tree.
+    ///
+    /// Files which are not Rust at all parse into trees consisting largely of error nodes.
+    pub fn error_node_ratio(&self) -> f64 {
+        if self.tree.is_empty() {
+            return 0.0;
+        }
+        let errors = self.tree.iter().filter(|node| node.node_type == "ERROR").count();
+        errors as f64 / self.tree.len() as f64
+    }
+
+    /// Returns the innermost node whose range contains `offset`, by scanning the whole tree.
+    ///
+    /// Ranges are half-open, so empty nodes never contain an offset. For repeated queries against
+    /// the same file, `build_offset_index` is faster.
+    pub fn node_at_offset(&self, offset: usize) -> Option<&Node> {
+        // In preorder, the last node containing the offset is the innermost one.
+        self.tree
+            .iter()
+            .rev()
+            .find(|node| node.range.offset <= offset && offset < node.range.end_offset)
+    }
+
+    /// Builds an index answering `node_at_offset` queries without scanning the whole tree.
+    pub fn build_offset_index(&self) -> OffsetIndex<'_> {
+        let mut by_start: Vec<usize> = (0..self.tree.len()).collect();
+        // Stable, so nodes starting at the same offset stay in preorder, outermost first.
+        by_start.sort_by_key(|&idx| self.tree[idx].range.offset);
+        OffsetIndex { tree: &self.tree, by_start, parents: self.parents() }
+    }
+
+    /// Returns the range and the number of descendant nodes of every top-level item, i.e. every
+    /// child of the root node, in source order.
+    ///
+    /// This shows which items dominate the size of the tree.
+    pub fn node_size_breakdown(&self) -> Vec<(Range, usize)> {
+        let parents = self.parents();
+        let mut descendants = vec![0; self.tree.len()];
+        // Children come after their parents in preorder, so a reverse pass sees every subtree
+        // complete before adding it to its parent.
+        for idx in (0..self.tree.len()).rev() {
+            if let Some(parent) = parents[idx] {
+                descendants[parent] += descendants[idx] + 1;
+            }
+        }
+        (0..self.tree.len())
+            .filter(|&idx| parents[idx] == Some(0))
+            .map(|idx| (self.tree[idx].range.clone(), descendants[idx]))
+            .collect()
+    }
+
+    /// Splits the file into one chunk per top-level item, i.e. per child of the root node, in
+    /// source order.
+    ///
+    /// Each chunk extends backwards over the comments between the previous item and its own, so
+    /// detached comments are attached to the item after them, and the last chunk extends over any
+    /// comments at the end of the file. Chunks never overlap and cover all of the content except
+    /// for the whitespace between them.
+    pub fn chunks_by_item(&self) -> Vec<Range> {
+        let parents = self.parents();
+        let items: Vec<&Range> = (0..self.tree.len())
+            .filter(|&idx| parents[idx] == Some(0))
+            .map(|idx| &self.tree[idx].range)
+            .collect();
+        let skip_whitespace = |from: usize, to: usize| {
+            let gap = self.content.get(from..to).unwrap_or_default();
+            from + (gap.len() - gap.trim_start().len())
+        };
+        let mut chunks = Vec::with_capacity(items.len());
+        let mut prev_end = 0;
+        for (i, item) in items.iter().enumerate() {
+            let mut end_offset = item.end_offset;
+            if i == items.len() - 1 {
+                end_offset = end_offset.max(self.content.trim_end().len());
+            }
+            let offset = skip_whitespace(prev_end, item.offset);
+            chunks.push(Range { offset, end_offset });
+            prev_end = end_offset;
+        }
+        chunks
+    }
+
+    /// Returns the name and the range of the argument token tree of every macro call captured by
+    /// the parser, in source order.
+    ///
+    /// The name is the macro path as written, without the `!`, and the range includes the
+    /// delimiters of the arguments.
+    pub fn macro_calls(&self) -> Vec<(&str, &Range)> {
+        self.metadata
+            .iter()
+            .filter(|entry| entry.key == METADATA_MACRO_CALL)
+            .map(|entry| (entry.value.as_str(), &entry.range))
+            .collect()
+    }
+
+    /// Computes the offset at which each line of `content` starts, the value stored in
+    /// `line_offsets`. Lines end after a `\n`, so content ending with one has an empty last line.
+    pub fn compute_line_offsets(&self) -> Vec<u32> {
+        let starts = self.content.match_indices('\n').map(|(idx, _)| idx as u32 + 1);
+        std::iter::once(0).chain(starts).collect()
+    }
+
+    /// Returns the range of the zero-based `line`, including its line break, or `None` past the
+    /// last line.
+    ///
+    /// This takes constant time if `line_offsets` are stored and scans `content` otherwise. The
+    /// last line ends at the end of `content`.
+    pub fn line_range(&self, line: usize) -> Option<Range> {
+        let computed;
+        let offsets = if self.line_offsets.is_empty() {
+            computed = self.compute_line_offsets();
+            &computed
+        } else {
+            &self.line_offsets
+        };
+        let offset = *offsets.get(line)? as usize;
+        let end_offset = offsets.get(line + 1).map_or(self.content.len(), |&end| end as usize);
+        Some(Range { offset, end_offset: end_offset.max(offset) })
+    }
+
+    /// Returns the number of lines `node` spans, counting the lines its start and end are on.
+    ///
+    /// Uses the `METADATA_LINE_SPAN` entry of the node if the parser captured one, which also
+    /// works for files stored without their content.
+    pub fn node_line_span(&self, node: &Node) -> usize {
+        if let Some(span) = self
+            .metadata_values(&node.range, METADATA_LINE_SPAN)
+            .first()
+            .and_then(|span| span.parse().ok())
+        {
+            return span;
+        }
+        let text = self.content.get(node.range.offset..node.range.end_offset).unwrap_or_default();
+        text.matches('\n').count() + 1
+    }
+
+    /// Returns the test functions of the file in source order, as captured by the parser.
+    pub fn tests(&self) -> Vec<TestItem> {
+        self.metadata
+            .iter()
+            .filter(|entry| entry.key == METADATA_TEST)
+            .map(|entry| TestItem { name: entry.value.clone(), range: entry.range.clone() })
+            .collect()
+    }
+
+    /// Returns every `mod name;` declaration of the file as its module path relative to the file
+    /// (`name`, or `outer::name` inside an inline `mod outer { .. }`) together with the paths of
+    /// the files it may refer to. Declarations with a `#[path]` attribute are skipped.
+    fn external_modules(&self) -> Vec<(String, [String; 2])> {
+        let parents = &self.parents();
+        let children = |idx: usize| {
+            (idx + 1..self.tree.len())
+                .take_while(move |&child| {
+                    self.tree[child].range.offset < self.tree[idx].range.end_offset
+                })
+                .filter(move |&child| parents[child] == Some(idx))
+                .map(|child| &self.tree[child])
+        };
+        let text = |node: &Node| self.content.get(node.range.offset..node.range.end_offset);
+        let name =
+            |idx: usize| children(idx).find(|child| child.node_type == "NAME").and_then(text);
+        let (dir, file_name) =
+            self.path.rsplit_once('/').map_or(("", &*self.path), |(dir, file)| (dir, file));
+        let mut base: Vec<&str> = dir.split('/').filter(|it| !it.is_empty()).collect();
+        if !matches!(file_name, "lib.rs" | "main.rs" | "mod.rs") {
+            base.push(file_name.strip_suffix(".rs").unwrap_or(file_name));
+        }
+        let mut modules = Vec::new();
+        for (idx, node) in self.tree.iter().enumerate() {
+            if node.node_type != "MODULE"
+                || children(idx).any(|child| child.node_type == "ITEM_LIST")
+            {
+                continue;
+            }
+            let is_path_attr = |child: &Node| {
+                child.node_type == "ATTR"
+                    && text(child).is_some_and(|attr| {
+                        attr.trim_start_matches("#[").trim_start().starts_with("path")
+                    })
+            };
+            if children(idx).any(is_path_attr) {
+                continue;
+            }
+            let mut segments = vec![];
+            let mut ancestor = Some(idx);
+            while let Some(module) = ancestor {
+                if self.tree[module].node_type == "MODULE" {
+                    let Some(name) = name(module) else {
+                        segments.clear();
+                        break;
+                    };
+                    segments.push(name);
+                }
+                ancestor = parents[module];
+            }
+            if segments.is_empty() {
+                continue;
+            }
+            segments.reverse();
+            let dir = base
+                .iter()
+                .chain(&segments[..segments.len() - 1])
+                .fold(String::new(), |acc, it| acc + it + "/");
+            let name = segments[segments.len() - 1];
+            modules.push((
+                segments.join("::"),
+                [format!("{dir}{name}.rs"), format!("{dir}{name}/mod.rs")],
+            ));
+        }
+        modules
+    }
+
+    /// Computes the index of the parent of each node in `tree`, based on range containment.
+    fn parents(&self) -> Vec<Option<usize>> {
+        let mut parents = Vec::with_capacity(self.tree.len());
+        let mut stack: Vec<usize> = Vec::new();
+        for node in &self.tree {
+            while let Some(&top) = stack.last() {
+                let top = &self.tree[top].range;
+                if top.offset <= node.range.offset && node.range.end_offset <= top.end_offset {
+                    break;
+                }
+                stack.pop();
+            }
+            parents.push(stack.last().copied());
+            stack.push(parents.len() - 1);
+        }
+        parents
+    }
+}
+
+/// An index over the nodes of a `File` for fast offset lookups, see `File::build_offset_index`.
+#[derive(Debug)]
+pub struct OffsetIndex<'a> {
+    tree: &'a [Node],
+    /// Node indices sorted by start offset.
+    by_start: Vec<usize>,
+    parents: Vec<Option<usize>>,
+}
+
+impl<'a> OffsetIndex<'a> {
+    /// Returns the innermost node whose range contains `offset`, like `File::node_at_offset`.
+    ///
+    /// Finds the last node starting at or before `offset` with a binary search, then walks up its
+    /// ancestors to the first one containing `offset`, so a query takes `O(log n + depth)`.
+    pub fn node_at_offset(&self, offset: usize) -> Option<&'a Node> {
+        let pos = self.by_start.partition_point(|&idx| self.tree[idx].range.offset <= offset);
+        let mut candidate = Some(*self.by_start.get(pos.checked_sub(1)?)?);
+        while let Some(idx) = candidate {
+            let range = &self.tree[idx].range;
+            if range.offset <= offset && offset < range.end_offset {
+                return Some(&self.tree[idx]);
+            }
+            candidate = self.parents[idx];
+        }
+        None
+    }
+}
+
+/// Options controlling how `Project::encode_with_options` writes an asset.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    /// Store node ranges compactly: each node record holds the distance back to its parent's
+    /// record, its offset relative to the parent's offset and its length, as LEB128 varints.
+    /// Children lie within their parents, so these values are usually tiny.
+    ///
+    /// Parents are derived from range containment as usual and the decoded ranges are the same.
+    pub delta_offsets: bool,
+}
+
+/// Options controlling how `Project::decode_with_options` reads an asset.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Keep file paths relative to the common root directory they were stored under instead of
+    /// reconstructing the full paths.
+    ///
+    /// Since version 5, the longest directory prefix shared by all paths of a segment is stored
+    /// once in its header and the per-file paths are stored relative to it.
+    pub relative_paths: bool,
+    /// Fail on bytes after the last segment that do not start another segment. By default they
+    /// are ignored with a warning. Such bytes usually come from accidentally concatenating files.
+    ///
+    /// Bytes only start another segment if they hold a complete header with the magic byte and a
+    /// supported version, so short or garbled trailing bytes are never decoded as a segment.
+    pub reject_trailing_bytes: bool,
+    /// Seek past the node records instead of decoding them, leaving every `File::tree` empty.
+    ///
+    /// Useful when only the file list, contents or metadata are needed. Node records have a fixed
+    /// size unless they store delta offsets, so usually the whole tree is skipped with one seek.
+    pub skip_trees: bool,
+    /// Seek past the annotation records instead of decoding them, leaving every `File::errors`
+    /// empty.
+    pub skip_errors: bool,
+}
+
+/// What `Project::identify` reports about an asset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AssetInfo {
+    /// Whether the asset starts with the magic byte. The other fields are only set if it does.
+    pub valid_magic: bool,
+    /// The format version of the first segment.
+    pub version: u32,
+    /// Whether the header is complete and `version` is one `Project::decode` can read. The fields
+    /// below are only set if so.
+    pub supported: bool,
+    /// The number of files in the first segment.
+    pub num_files: usize,
+    /// Whether the first file record of the first segment stores delta offsets, see
+    /// `EncodeOptions::delta_offsets`. This is the only compact layout, the format has no general
+    /// compression.
+    ///
+    /// The layout is a per-file flag with no header-level counterpart, so this is always false for
+    /// a segment without files, and appended segments may use a different layout.
+    pub delta_offsets: bool,
+    /// Whether the first segment has a file index, see `Project::decode_where`.
+    pub has_index: bool,
+    /// The size of the asset in bytes.
+    pub size: u64,
+}
+
+/// The number of bytes each section of an encoded asset takes, see `Project::section_sizes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// The segment header and the end marker.
+    pub header: usize,
+    /// The per-file fields before the records: path, content, record counts, flags, shebang and
+    /// edition.
+    pub files: usize,
+    /// The node records, including their subtree hashes.
+    pub nodes: usize,
+    /// The annotation records, including their severities and contexts.
+    pub errors: usize,
+    /// The literal records.
+    pub literals: usize,
+    /// The metadata records.
+    pub metadata: usize,
+    /// The stored line offsets, see `File::line_offsets`.
+    pub line_offsets: usize,
+    /// The string table, which holds the text the records refer to.
+    pub string_table: usize,
+    /// The file index, which locates and checksums each file record.
+    pub index: usize,
+}
+
+impl SectionSizes {
+    /// The size of the whole asset.
+    pub fn total(&self) -> usize {
+        self.header
+            + self.files
+            + self.nodes
+            + self.errors
+            + self.literals
+            + self.metadata
+            + self.line_offsets
+            + self.string_table
+            + self.index
+    }
 }
 
 /// Represents a collection of files which can be encoded as an asset.
@@ -58,118 +922,1358 @@ pub struct Project {
     pub files: Vec<File>,
 }
 
-impl Project {
-    pub fn encode<W: Write + Seek>(&self, mut writer: W) -> anyhow::Result<()> {
-        // String table: collect all unique strings and assign indices
-        let mut string_table = StringTableBuilder::default();
-        for file in &self.files {
-            string_table.add(&file.path);
-            string_table.add(&file.content);
-            for node in &file.tree {
-                string_table.add(&node.node_type);
-            }
-            for ann in &file.errors {
-                string_table.add(&ann.text);
+impl Project {
+    /// Appends externally produced annotations (e.g. lints from a separate clippy run) to the
+    /// files they belong to, keyed by path.
+    ///
+    /// The relative order of the merged annotations is preserved. Annotations for paths that are
+    /// not part of the project are dropped with a warning. If `dedup` is set, annotations equal
+    /// to one the file already carries are skipped.
+    pub fn merge_annotations(
+        &mut self,
+        mut extra: FxHashMap<String, Vec<Annotation>>,
+        dedup: bool,
+    ) {
+        for file in &mut self.files {
+            let Some(annotations) = extra.remove(&file.path) else { continue };
+            for ann in annotations {
+                if dedup && file.errors.contains(&ann) {
+                    continue;
+                }
+                file.errors.push(ann);
+            }
+        }
+        for (path, annotations) in extra {
+            tracing::warn!(
+                "dropping {} annotation(s) for unknown path {}",
+                annotations.len(),
+                path
+            );
+        }
+    }
+
+    /// Fills in the content of files that have none from the file with the same path in
+    /// `content_source`, e.g. to recombine a tree-only asset with a content-only one.
+    ///
+    /// Files that already have content are left unchanged. Returns the paths that are only part
+    /// of one of the two projects, those of `self` first, each in order.
+    pub fn merge_content(&mut self, content_source: &Project) -> Vec<String> {
+        let mut sources: FxHashMap<&str, &File> =
+            content_source.files.iter().map(|file| (file.path.as_str(), file)).collect();
+        let mut unmatched = Vec::new();
+        for file in &mut self.files {
+            match sources.remove(file.path.as_str()) {
+                Some(source) if file.content.is_empty() => file.content = source.content.clone(),
+                Some(_) => {}
+                None => unmatched.push(file.path.clone()),
+            }
+        }
+        unmatched.extend(
+            content_source
+                .files
+                .iter()
+                .filter(|file| sources.contains_key(file.path.as_str()))
+                .map(|file| file.path.clone()),
+        );
+        unmatched
+    }
+
+    /// Iterates over the annotations of all files, together with the path of the owning file.
+    pub fn all_annotations(&self) -> impl Iterator<Item = (&str, &Annotation)> {
+        self.files.iter().flat_map(|file| file.errors.iter().map(|ann| (file.path.as_str(), ann)))
+    }
+
+    /// Like `all_annotations`, but only yields annotations with `Severity::Error`.
+    pub fn all_errors(&self) -> impl Iterator<Item = (&str, &Annotation)> {
+        self.all_annotations().filter(|(_, ann)| ann.severity == Severity::Error)
+    }
+
+    /// Counts how often each error text occurs across all files, e.g. to find the most common
+    /// parse problems. Only annotations with `Severity::Error` are counted, see `all_errors`.
+    ///
+    /// Texts are counted as they are. See `normalized_error_message_histogram` to group texts that
+    /// only differ in the positions they mention.
+    pub fn error_message_histogram(&self) -> BTreeMap<String, usize> {
+        let mut histogram = BTreeMap::new();
+        for (_, ann) in self.all_errors() {
+            *histogram.entry(ann.text.clone()).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Like `error_message_histogram`, but replaces every run of digits in the texts with `N`
+    /// first, so that e.g. `unexpected token at 3:5` and `unexpected token at 10:2` are counted
+    /// together as `unexpected token at N:N`.
+    pub fn normalized_error_message_histogram(&self) -> BTreeMap<String, usize> {
+        let mut histogram = BTreeMap::new();
+        for (_, ann) in self.all_errors() {
+            *histogram.entry(strip_positions(&ann.text)).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Rewrites the paths starting with `from_prefix` to start with `to_prefix` instead, e.g. after
+    /// moving an asset to a machine with a different directory layout.
+    ///
+    /// The prefix only matches whole path components, so `/src` matches `/src/lib.rs` but not
+    /// `/src2/lib.rs`. Paths that don't match are left unchanged. Returns the number of rewritten
+    /// paths.
+    pub fn rebase_paths(&mut self, from_prefix: &str, to_prefix: &str) -> usize {
+        let mut rebased = 0;
+        for file in &mut self.files {
+            let Some(rest) = file.path.strip_prefix(from_prefix) else { continue };
+            let at_boundary = rest.is_empty()
+                || rest.starts_with(['/', '\\'])
+                || from_prefix.ends_with(['/', '\\']);
+            if at_boundary {
+                file.path = format!("{to_prefix}{rest}");
+                rebased += 1;
+            }
+        }
+        rebased
+    }
+
+    /// Returns the files whose path matches the glob `pattern`, in order.
+    ///
+    /// The pattern is matched against the whole stored path and `*` does not match `/`, so
+    /// `src/**/*.rs` selects the Rust files under a relative `src` directory and `**/src/**/*.rs`
+    /// those under any `src` directory. Fails if `pattern` is not a valid glob.
+    pub fn files_matching(&self, pattern: &str) -> anyhow::Result<Vec<&File>> {
+        let matcher = globset::GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid glob {pattern:?}"))?
+            .compile_matcher();
+        Ok(self.files.iter().filter(|file| matcher.is_match(&file.path)).collect())
+    }
+
+    /// Keeps only the files whose path is in `keep`, in their current order.
+    pub fn retain_paths(&mut self, keep: &FxHashSet<String>) {
+        self.files.retain(|file| keep.contains(&file.path));
+    }
+
+    /// Removes the files whose path is in `drop`, keeping the others in their current order.
+    pub fn remove_paths(&mut self, drop: &FxHashSet<String>) {
+        self.files.retain(|file| !drop.contains(&file.path));
+    }
+
+    /// Renames node kinds across all files, replacing every `node_type` that is a key of `map` by
+    /// its value. This migrates assets between parser versions that renamed kinds.
+    ///
+    /// Renames are applied once, not transitively, so swapping two names works. Subtree hashes
+    /// are not recomputed and keep reflecting the old names.
+    pub fn rename_kinds(&mut self, map: &FxHashMap<String, String>) {
+        for node in self.files.iter_mut().flat_map(|file| &mut file.tree) {
+            if let Some(new) = map.get(&node.node_type) {
+                node.node_type.clone_from(new);
+            }
+        }
+    }
+
+    /// Reports every node whose `node_type` is not the name of a `SyntaxKind` of this parser, as
+    /// the path of its file, its index in the tree and the kind.
+    ///
+    /// Unknown kinds point to an asset written by an incompatible parser version, or to corrupted
+    /// string indices. Files are checked in order, each file's nodes in tree order.
+    pub fn validate_kinds(&self) -> Vec<(String, usize, String)> {
+        let known: FxHashSet<String> = (0..SyntaxKind::__LAST as u16)
+            .map(|raw| format!("{:?}", SyntaxKind::from(raw)))
+            .collect();
+        let mut unknown = Vec::new();
+        for file in &self.files {
+            for (idx, node) in file.tree.iter().enumerate() {
+                if !known.contains(&node.node_type) {
+                    unknown.push((file.path.clone(), idx, node.node_type.clone()));
+                }
+            }
+        }
+        unknown
+    }
+
+    /// Sorts the files with the comparator `cmp`. The sort is stable, so equal files stay in
+    /// their current order.
+    pub fn sort_files_by<F: FnMut(&File, &File) -> Ordering>(&mut self, cmp: F) {
+        self.files.sort_by(cmp);
+    }
+
+    /// Sorts the files by their number of annotations, most first.
+    pub fn sort_by_error_count_desc(&mut self) {
+        self.sort_files_by(|a, b| b.errors.len().cmp(&a.errors.len()));
+    }
+
+    /// Sorts the files by their number of nodes, most first.
+    pub fn sort_by_node_count_desc(&mut self) {
+        self.sort_files_by(|a, b| b.tree.len().cmp(&a.tree.len()));
+    }
+
+    /// Removes the nodes for which `keep` returns false from the tree of every file. The remaining
+    /// nodes are left unchanged and stay in preorder.
+    ///
+    /// Trees store no parent links, ancestry follows from range containment, so after the removal
+    /// the parent of a kept node is the innermost kept node containing it. Removing the root node
+    /// leaves a forest. Subtree hashes of kept nodes still describe their original subtrees.
+    pub fn filter_nodes<F: Fn(&Node) -> bool>(&mut self, keep: F) {
+        for file in &mut self.files {
+            file.tree.retain(&keep);
+        }
+    }
+
+    /// Computes a stable digest of the project, suitable as a cache key.
+    ///
+    /// The digest does not depend on the order of `files` or on how the project is encoded: files
+    /// are hashed sorted by path, each contributing its path, a hash of its content, and its nodes,
+    /// annotations and literals. Metadata entries and annotation contexts are derived from the tree
+    /// and the content and are ignored, as is anything that is not part of `File`, such as file
+    /// modification times.
+    pub fn content_digest(&self) -> [u8; 32] {
+        let mut files: Vec<&File> = self.files.iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut hasher = Sha256::new();
+        for file in files {
+            hash_str(&mut hasher, &file.path);
+            hasher.update(Sha256::digest(&file.content));
+            hasher.update((file.tree.len() as u64).to_le_bytes());
+            for node in &file.tree {
+                hash_range(&mut hasher, &node.range);
+                hash_str(&mut hasher, &node.node_type);
+            }
+            hasher.update((file.errors.len() as u64).to_le_bytes());
+            for ann in &file.errors {
+                hash_range(&mut hasher, &ann.range);
+                hash_str(&mut hasher, &ann.text);
+                hasher.update([ann.severity.to_u8()]);
+            }
+            hasher.update((file.literals.len() as u64).to_le_bytes());
+            for lit in &file.literals {
+                hash_range(&mut hasher, &lit.range);
+                hash_str(&mut hasher, &lit.kind);
+                hash_str(&mut hasher, &lit.value);
+            }
+        }
+        hasher.finalize().into()
+    }
+
+    pub fn encode<W: Write + Seek>(&self, writer: W) -> anyhow::Result<()> {
+        self.encode_with_options(writer, &EncodeOptions::default())
+    }
+
+    pub fn encode_with_options<W: Write + Seek>(
+        &self,
+        writer: W,
+        options: &EncodeOptions,
+    ) -> anyhow::Result<()> {
+        encode_segment(&self.files, writer, options)
+    }
+
+    /// Encodes the project into a new buffer.
+    pub fn encode_to_vec(&self, options: &EncodeOptions) -> anyhow::Result<Vec<u8>> {
+        let mut buf = io::Cursor::new(Vec::with_capacity(self.encoded_size(options)));
+        self.encode_with_options(&mut buf, options)?;
+        Ok(buf.into_inner())
+    }
+
+    /// Computes the exact number of bytes `encode_with_options` produces with `options`, without
+    /// encoding the project.
+    ///
+    /// This still collects the unique strings of the project, but does not copy any content.
+    pub fn encoded_size(&self, options: &EncodeOptions) -> usize {
+        segment_sections(&self.files, options).total()
+    }
+
+    /// Like `encoded_size`, but split into the sections of the encoding, to see which parts of
+    /// the format an asset spends its bytes on.
+    pub fn section_sizes(&self, options: &EncodeOptions) -> SectionSizes {
+        segment_sections(&self.files, options)
+    }
+
+    /// Appends `files` to an existing asset as a new, self-contained segment.
+    ///
+    /// Each segment carries its own header and string table, so appending never rewrites what is
+    /// already in `writer`, at the cost of strings shared between segments being stored once per
+    /// segment. `decode` reads all segments of an asset and concatenates their files. Appending to
+    /// an empty writer produces a regular single-segment asset.
+    pub fn append_segment<W: Write + Seek>(mut writer: W, files: &[File]) -> anyhow::Result<()> {
+        writer.seek(SeekFrom::End(0))?;
+        encode_segment(files, writer, &EncodeOptions::default())
+    }
+
+    /// Returns the `mod name;` declarations that refer to a file which is not part of the project,
+    /// as pairs of the declaring file's path and the module path (`name`, or `outer::name` when
+    /// declared inside an inline module).
+    ///
+    /// A declaration is resolved if either `name.rs` or `name/mod.rs` exists in the directory
+    /// the declaring file owns: its own directory for `lib.rs`, `main.rs` and `mod.rs`, and a
+    /// directory named after it otherwise. Paths are compared as stored, with `/` separators.
+    /// Declarations with a `#[path]` attribute are not checked.
+    pub fn missing_modules(&self) -> Vec<(String, String)> {
+        let paths: FxHashSet<&str> = self.files.iter().map(|file| file.path.as_str()).collect();
+        let mut missing = Vec::new();
+        for file in &self.files {
+            for (module, candidates) in file.external_modules() {
+                if !candidates.iter().any(|candidate| paths.contains(candidate.as_str())) {
+                    missing.push((file.path.clone(), module));
+                }
+            }
+        }
+        missing
+    }
+
+    /// Writes every annotation as a `path:line:col: severity: message` line, the way compilers
+    /// report diagnostics, for tools like `grep`.
+    ///
+    /// Lines are sorted by path, then by offset. Lines and columns are one-based, and columns
+    /// count characters like rustc does.
+    pub fn write_annotations_plain<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
+        use ide_db::line_index::{LineIndex, WideEncoding};
+
+        let mut files: Vec<&File> = self.files.iter().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        for file in files {
+            let index = LineIndex::new(&file.content);
+            let mut annotations: Vec<&Annotation> = file.errors.iter().collect();
+            annotations.sort_by_key(|ann| ann.range.offset);
+            for ann in annotations {
+                let offset = syntax::TextSize::new(ann.range.offset.min(file.content.len()) as u32);
+                let (line, col) = index
+                    .try_line_col(offset)
+                    .and_then(|line_col| index.to_wide(WideEncoding::Utf32, line_col))
+                    .map_or((0, 0), |wide| (wide.line, wide.col));
+                writeln!(
+                    writer,
+                    "{}:{}:{}: {}: {}",
+                    file.path,
+                    line + 1,
+                    col + 1,
+                    ann.severity.as_str(),
+                    ann.text
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the errors and warnings of all files as a SARIF 2.1.0 log with a single run, the
+    /// format consumed by code scanning services. Notes are left out.
+    ///
+    /// Each result carries the file path as a relative URI (with `/` separators) and a
+    /// one-based region; columns count UTF-16 code units, as declared by the run's `columnKind`.
+    #[cfg(feature = "sarif")]
+    pub fn to_sarif<W: Write>(&self, writer: W) -> anyhow::Result<()> {
+        use ide_db::line_index::{LineIndex, WideEncoding};
+        use serde_json::json;
+
+        let mut results = Vec::new();
+        for file in &self.files {
+            let index = LineIndex::new(&file.content);
+            let position = |offset: usize| {
+                let offset = syntax::TextSize::new(offset.min(file.content.len()) as u32);
+                let line_col = index.try_line_col(offset)?;
+                let wide = index.to_wide(WideEncoding::Utf16, line_col)?;
+                Some((wide.line + 1, wide.col + 1))
+            };
+            for ann in &file.errors {
+                let level = match ann.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                    Severity::Note => continue,
+                };
+                let mut region = serde_json::Map::new();
+                if let (Some(start), Some(end)) =
+                    (position(ann.range.offset), position(ann.range.end_offset))
+                {
+                    region.insert("startLine".to_owned(), start.0.into());
+                    region.insert("startColumn".to_owned(), start.1.into());
+                    region.insert("endLine".to_owned(), end.0.into());
+                    region.insert("endColumn".to_owned(), end.1.into());
+                }
+                results.push(json!({
+                    "level": level,
+                    "message": { "text": ann.text },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": file.path.replace('\\', "/") },
+                            "region": region,
+                        }
+                    }],
+                }));
+            }
+        }
+        let log = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "rust-analyzer",
+                        "informationUri": "https://rust-analyzer.github.io/",
+                    }
+                },
+                "columnKind": "utf16CodeUnits",
+                "results": results,
+            }],
+        });
+        serde_json::to_writer_pretty(writer, &log)?;
+        Ok(())
+    }
+
+    /// Returns the encoding versions `decode` can read, oldest first. The last one is
+    /// `CURRENT_ASSET_VERSION`.
+    pub fn supported_versions() -> &'static [u32] {
+        SUPPORTED_ASSET_VERSIONS
+    }
+
+    pub fn decode<R: Read + Seek>(reader: R) -> anyhow::Result<Self> {
+        Self::decode_with_options(reader, &DecodeOptions::default())
+    }
+
+    pub fn decode_with_options<R: Read + Seek>(
+        reader: R,
+        options: &DecodeOptions,
+    ) -> anyhow::Result<Self> {
+        decode_segments(reader, options, None)
+    }
+
+    /// Decodes only the files whose path matches `pred`, seeking past the records of the others.
+    ///
+    /// Since version 11, each segment has an index of its file records, so only the path of an
+    /// unmatched file is read. Matched records are verified against the checksum stored in the
+    /// index and a mismatch is an error. Older assets have no index and no checksums: all their
+    /// files are decoded and then filtered.
+    pub fn decode_where<R: Read + Seek, F: Fn(&str) -> bool>(
+        reader: R,
+        pred: F,
+    ) -> anyhow::Result<Self> {
+        decode_segments(reader, &DecodeOptions::default(), Some(&pred))
+    }
+
+    /// Reads the string tables of an asset without decoding its files.
+    ///
+    /// Only the segment headers and tables are read, so this is cheap even for large assets. The
+    /// strings of all segments are returned in order, each segment's strings in index order, so a
+    /// string shared between segments appears once per segment. Reading stops at the first byte
+    /// that does not start a segment.
+    pub fn read_string_table<R: Read + Seek>(mut reader: R) -> anyhow::Result<Vec<String>> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut strings = Vec::new();
+        loop {
+            let header = read_segment_header(&mut reader)?;
+            reader.seek(SeekFrom::Start(header.string_table_offset as u64))?;
+            let table = StringTable::read(&mut reader).context("truncated string table")?;
+            strings.extend(table.vec);
+            if header.version >= 11 {
+                let index_len = header.num_files * IndexEntry::SIZE;
+                reader.seek(SeekFrom::Current(index_len as i64))?;
+            }
+            if header.version >= 9 {
+                reader.seek(SeekFrom::Current(END_MARKER.len() as i64))?;
+            }
+            let pos = reader.stream_position()?;
+            let mut next = [0; 1];
+            if pos >= end || reader.read_exact(&mut next).is_err() || next[0] != MAGIC {
+                break;
+            }
+            reader.seek(SeekFrom::Start(pos))?;
+        }
+        Ok(strings)
+    }
+
+    /// Identifies an asset from its header, like the `file` command.
+    ///
+    /// Only the header of the first segment and the flags of its first file record are read, so
+    /// this is the cheapest way to probe an unknown file. Fails only if seeking fails, an asset
+    /// that is too short for its header is reported as unsupported.
+    pub fn identify<R: Read + Seek>(mut reader: R) -> anyhow::Result<AssetInfo> {
+        let start = reader.stream_position()?;
+        let size = reader.seek(SeekFrom::End(0))? - start;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut info = AssetInfo { size, ..Default::default() };
+        let mut magic = [0; 1];
+        if reader.read_exact(&mut magic).is_err() || magic[0] != MAGIC {
+            return Ok(info);
+        }
+        info.valid_magic = true;
+        reader.seek(SeekFrom::Start(start))?;
+        let Ok(header) = read_segment_header(&mut reader) else {
+            reader.seek(SeekFrom::Start(start + 1))?;
+            info.version = read_u32(&mut reader).unwrap_or_default();
+            return Ok(info);
+        };
+        info.version = header.version;
+        info.supported = true;
+        info.num_files = header.num_files;
+        info.has_index = header.version >= 11;
+        if header.num_files > 0 && header.version >= 12 {
+            // The path and content indices come before the flags.
+            skip_bytes(&mut reader, 2 * INT_SIZE)?;
+            let flags = read_u32(&mut reader).unwrap_or_default();
+            info.delta_offsets = flags & FILE_FLAG_DELTA_OFFSETS != 0;
+        }
+        Ok(info)
+    }
+
+    /// Decodes as much as possible of an asset whose dump was interrupted.
+    ///
+    /// The string table is written last, so a truncated asset usually has intact file records but
+    /// no table, or a header still pointing at the placeholder offset. In that case the records are
+    /// decoded anyway and every string is replaced by a `<string:idx>` placeholder, including the
+    /// paths, which lose their common root. File records cut off mid-way are dropped. Assets that
+    /// are intact decode exactly like with `decode`.
+    ///
+    /// Only a broken first segment is an error. Decoding stops with a warning at the first later
+    /// segment that cannot be decoded, such as trailing garbage, and keeps the files before it.
+    pub fn decode_recover<R: Read + Seek>(mut reader: R) -> anyhow::Result<Self> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut files = decode_segment(&mut reader, &DecodeOptions::default(), true, None)?;
+        loop {
+            let pos = reader.stream_position()?;
+            if pos >= end {
+                break;
+            }
+            match decode_segment(&mut reader, &DecodeOptions::default(), true, None) {
+                Ok(segment) => files.extend(segment),
+                Err(err) => {
+                    tracing::warn!(
+                        "ignoring {} undecodable bytes at offset {pos}: {err}",
+                        end - pos
+                    );
+                    break;
+                }
+            }
+        }
+        Ok(Project { files })
+    }
+}
+
+impl IntoIterator for Project {
+    type Item = File;
+    type IntoIter = std::vec::IntoIter<File>;
+
+    /// Consumes the project, yielding its files in order.
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Project {
+    type Item = &'a File;
+    type IntoIter = std::slice::Iter<'a, File>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+/// Several named projects stored in one asset, e.g. one per crate of a workspace.
+///
+/// The asset starts with a directory of the projects, sorted by name, each with the location of
+/// its encoding. The projects follow, each encoded like a standalone `Project`, so one of them
+/// can be decoded with `decode_project` without reading the others.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MultiProject {
+    pub projects: FxHashMap<String, Project>,
+}
+
+/// A directory entry of a `MultiProject` asset.
+struct MultiEntry {
+    name: String,
+    /// The offset of the project's encoding from the start of the asset.
+    offset: u64,
+    /// The length of the project's encoding in bytes.
+    len: u64,
+}
+
+impl MultiProject {
+    pub fn encode<W: Write + Seek>(&self, writer: W) -> anyhow::Result<()> {
+        self.encode_with_options(writer, &EncodeOptions::default())
+    }
+
+    /// Encodes every project with `options`.
+    pub fn encode_with_options<W: Write + Seek>(
+        &self,
+        mut writer: W,
+        options: &EncodeOptions,
+    ) -> anyhow::Result<()> {
+        let mut names: Vec<&String> = self.projects.keys().collect();
+        names.sort();
+        let start = writer.stream_position()?;
+        writer.write_all(&[MULTI_MAGIC])?;
+        writer.write_all(&MULTI_PROJECT_VERSION.to_le_bytes())?;
+        writer.write_all(&u32::try_from(names.len())?.to_le_bytes())?;
+        // Write the directory with placeholder locations, and the projects after it, one at a time
+        let directory_pos = writer.stream_position()?;
+        let mut entries: Vec<_> = names
+            .into_iter()
+            .map(|name| MultiEntry { name: name.clone(), offset: 0, len: 0 })
+            .collect();
+        for entry in &entries {
+            entry.write(&mut writer)?;
+        }
+        for entry in &mut entries {
+            let encoded = self.projects[&entry.name].encode_to_vec(options)?;
+            entry.offset = writer.stream_position()? - start;
+            entry.len = encoded.len() as u64;
+            writer.write_all(&encoded)?;
+        }
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(directory_pos))?;
+        for entry in &entries {
+            entry.write(&mut writer)?;
+        }
+        writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    /// Decodes all projects of the asset.
+    pub fn decode<R: Read + Seek>(mut reader: R) -> anyhow::Result<Self> {
+        let (start, entries) = read_multi_directory(&mut reader)?;
+        let mut projects = FxHashMap::with_capacity_and_hasher(entries.len(), Default::default());
+        for entry in entries {
+            let project = decode_multi_entry(&mut reader, start, &entry)?;
+            projects.insert(entry.name, project);
+        }
+        Ok(MultiProject { projects })
+    }
+
+    /// Returns the names of the projects of the asset, sorted, reading only its directory.
+    pub fn project_names<R: Read + Seek>(mut reader: R) -> anyhow::Result<Vec<String>> {
+        let (_, entries) = read_multi_directory(&mut reader)?;
+        Ok(entries.into_iter().map(|entry| entry.name).collect())
+    }
+
+    /// Decodes the project called `name`, reading only the directory and that project's
+    /// encoding. Returns `None` if the asset has no such project.
+    pub fn decode_project<R: Read + Seek>(
+        mut reader: R,
+        name: &str,
+    ) -> anyhow::Result<Option<Project>> {
+        let (start, entries) = read_multi_directory(&mut reader)?;
+        let Some(entry) = entries.iter().find(|entry| entry.name == name) else { return Ok(None) };
+        decode_multi_entry(&mut reader, start, entry).map(Some)
+    }
+}
+
+impl MultiEntry {
+    fn read<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+        let mut name = vec![0; read_u32(r)? as usize];
+        r.read_exact(&mut name)?;
+        Ok(MultiEntry { name: String::from_utf8(name)?, offset: read_u64(r)?, len: read_u64(r)? })
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> anyhow::Result<()> {
+        w.write_all(&u32::try_from(self.name.len())?.to_le_bytes())?;
+        w.write_all(self.name.as_bytes())?;
+        w.write_all(&self.offset.to_le_bytes())?;
+        w.write_all(&self.len.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+/// Reads the header and directory of a `MultiProject` asset, returning the position the asset
+/// starts at and the entries.
+fn read_multi_directory<R: Read + Seek>(reader: &mut R) -> anyhow::Result<(u64, Vec<MultiEntry>)> {
+    let start = reader.stream_position()?;
+    let magic = read_u8(reader)?;
+    if magic != MULTI_MAGIC {
+        anyhow::bail!(
+            "invalid magic byte: expected 0xdf for a multi-project asset, got {:x}",
+            magic
+        );
+    }
+    let version = read_u32(reader)?;
+    if version != MULTI_PROJECT_VERSION {
+        anyhow::bail!(
+            "unsupported multi-project version: expected {}, got {}",
+            MULTI_PROJECT_VERSION,
+            version
+        );
+    }
+    let num_projects = read_u32(reader)?;
+    let entries: anyhow::Result<Vec<_>> =
+        (0..num_projects).map(|_| MultiEntry::read(reader)).collect();
+    Ok((start, entries.context("truncated multi-project directory")?))
+}
+
+/// Decodes the project of `entry`. Its encoding is read into a buffer first, because offsets in
+/// a project's encoding are relative to the start of that encoding.
+fn decode_multi_entry<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    entry: &MultiEntry,
+) -> anyhow::Result<Project> {
+    reader.seek(SeekFrom::Start(start + entry.offset))?;
+    let mut encoded = vec![0; usize::try_from(entry.len)?];
+    reader
+        .read_exact(&mut encoded)
+        .with_context(|| format!("truncated encoding of project {}", entry.name))?;
+    Project::decode(io::Cursor::new(encoded))
+        .with_context(|| format!("failed to decode project {}", entry.name))
+}
+
+/// Decodes all segments of an asset, keeping only the files matching `filter` if set.
+fn decode_segments<R: Read + Seek>(
+    mut reader: R,
+    options: &DecodeOptions,
+    filter: Option<&dyn Fn(&str) -> bool>,
+) -> anyhow::Result<Project> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+    let mut files = Vec::new();
+    loop {
+        files.extend(decode_segment(&mut reader, options, false, filter)?);
+        let pos = reader.stream_position()?;
+        if pos >= end {
+            break;
+        }
+        let starts_segment = end - pos >= (1 + 3 * INT_SIZE) as u64
+            && read_u8(&mut reader)? == MAGIC
+            && SUPPORTED_ASSET_VERSIONS.contains(&(read_int(&mut reader)? as u32));
+        reader.seek(SeekFrom::Start(pos))?;
+        if !starts_segment {
+            if options.reject_trailing_bytes {
+                anyhow::bail!("unexpected {} trailing bytes after the last segment", end - pos);
+            }
+            tracing::warn!("ignoring {} trailing bytes after the last segment", end - pos);
+            break;
+        }
+    }
+    Ok(Project { files })
+}
+
+/// Collects the unique strings of a segment whose paths are stored relative to `root`, in the
+/// order `encode_segment` assigns their indices.
+fn segment_string_table(files: &[File], root: &str) -> StringTableBuilder {
+    let mut string_table = StringTableBuilder::default();
+    string_table.add(root);
+    for file in files {
+        string_table.add(&file.path[root.len()..]);
+        string_table.add(&file.content);
+        for node in &file.tree {
+            string_table.add(&node.node_type);
+        }
+        for ann in &file.errors {
+            string_table.add(&ann.text);
+        }
+        for context in file.errors.iter().filter_map(|ann| ann.context.as_ref()) {
+            string_table.add(context);
+        }
+        if let Some(shebang) = &file.shebang {
+            string_table.add(shebang);
+        }
+        for lit in &file.literals {
+            string_table.add(&lit.kind);
+            string_table.add(&lit.value);
+        }
+        for entry in &file.metadata {
+            string_table.add(&entry.key);
+            string_table.add(&entry.value);
+        }
+    }
+    string_table
+}
+
+/// Returns the longest directory prefix shared by the paths of `files` that `encode_segment`
+/// stores once in the header.
+fn segment_root(files: &[File]) -> &str {
+    common_dir_prefix(files.iter().map(|file| file.path.as_str()))
+}
+
+/// Computes the number of bytes `encode_segment` writes for `files`, per section.
+fn segment_sections(files: &[File], options: &EncodeOptions) -> SectionSizes {
+    let root = segment_root(files);
+    let string_table = segment_string_table(files, root);
+    let int = INT_SIZE;
+    let mut sizes = SectionSizes {
+        // Magic, version, string table offset, number of files, the root and the end marker.
+        header: 1 + 4 * int + END_MARKER.len(),
+        ..Default::default()
+    };
+    for file in files {
+        // Path, content, the record counts, flags, the optional shebang and the edition.
+        sizes.files += 6 * int + 4 + if file.shebang.is_some() { int } else { 0 } + 1;
+        let has_hashes = file.tree.iter().any(|node| node.subtree_hash.is_some());
+        if options.delta_offsets {
+            let ranges: usize =
+                delta_node_fields(file).iter().flatten().map(|&value| leb128_len(value)).sum();
+            sizes.nodes += ranges + file.tree.len() * (int + if has_hashes { 8 } else { 0 });
+        } else {
+            sizes.nodes += file.tree.len() * (3 * int + if has_hashes { 8 } else { 0 });
+        }
+        sizes.errors += file.errors.len() * (3 * int + 2);
+        sizes.errors += file.errors.iter().filter(|ann| ann.context.is_some()).count() * int;
+        sizes.literals += file.literals.len() * 4 * int;
+        sizes.metadata += file.metadata.len() * 4 * int;
+        if !file.line_offsets.is_empty() {
+            sizes.line_offsets += int + file.line_offsets.len() * 4;
+        }
+    }
+    sizes.string_table = int + string_table.vec.iter().map(|s| int + s.len()).sum::<usize>();
+    sizes.index = files.len() * IndexEntry::SIZE;
+    sizes
+}
+
+fn encode_segment<W: Write + Seek>(
+    files: &[File],
+    mut writer: W,
+    options: &EncodeOptions,
+) -> anyhow::Result<()> {
+    let root = segment_root(files);
+    let string_table = segment_string_table(files, root);
+    // Write header
+    writer.write_all(&[MAGIC])?;
+    write_int(&mut writer, CURRENT_ASSET_VERSION as usize)?;
+    let string_table_offset_pos = writer.stream_position()?;
+    write_int(&mut writer, 0)?; // placeholder for string table offset
+    write_int(&mut writer, files.len())?;
+    write_int(&mut writer, string_table.idx(root)?)?;
+    // Write files, each record buffered so that the index can checksum it
+    let mut index = Vec::with_capacity(files.len());
+    let mut record = Vec::new();
+    for file in files {
+        let has_hashes = file.tree.iter().any(|node| node.subtree_hash.is_some());
+        if has_hashes && file.tree.iter().any(|node| node.subtree_hash.is_none()) {
+            anyhow::bail!("file {} has subtree hashes for only some of its nodes", file.path);
+        }
+        let deltas = if options.delta_offsets {
+            if let Some(node) =
+                file.tree.iter().find(|node| node.range.end_offset < node.range.offset)
+            {
+                anyhow::bail!("node {:?} in {} ends before it starts", node.range, file.path);
+            }
+            Some(delta_node_fields(file))
+        } else {
+            None
+        };
+        record.clear();
+        write_int(&mut record, string_table.idx(&file.path[root.len()..])?)?;
+        write_int(&mut record, string_table.idx(&file.content)?)?;
+        let mut flags = 0;
+        if has_hashes {
+            flags |= FILE_FLAG_SUBTREE_HASHES;
+        }
+        if file.shebang.is_some() {
+            flags |= FILE_FLAG_SHEBANG;
+        }
+        if deltas.is_some() {
+            flags |= FILE_FLAG_DELTA_OFFSETS;
+        }
+        if !file.line_offsets.is_empty() {
+            flags |= FILE_FLAG_LINE_OFFSETS;
+        }
+        record.write_all(&flags.to_le_bytes())?;
+        if let Some(shebang) = &file.shebang {
+            write_int(&mut record, string_table.idx(shebang)?)?;
+        }
+        record.write_all(&[file.edition as u8])?;
+        write_int(&mut record, file.tree.len())?;
+        for (idx, node) in file.tree.iter().enumerate() {
+            match &deltas {
+                Some(deltas) => {
+                    for &value in &deltas[idx] {
+                        write_leb128(&mut record, value)?;
+                    }
+                }
+                None => {
+                    write_int(&mut record, node.range.offset)?;
+                    write_int(&mut record, node.range.end_offset)?;
+                }
+            }
+            write_int(&mut record, string_table.idx(&node.node_type)?)?;
+            if let Some(hash) = node.subtree_hash.filter(|_| has_hashes) {
+                record.write_all(&hash.to_le_bytes())?;
+            }
+        }
+        write_int(&mut record, file.errors.len())?;
+        for ann in &file.errors {
+            write_int(&mut record, ann.range.offset)?;
+            write_int(&mut record, ann.range.end_offset)?;
+            write_int(&mut record, string_table.idx(&ann.text)?)?;
+            let flags = if ann.context.is_some() { ANNOTATION_FLAG_CONTEXT } else { 0 };
+            record.write_all(&[ann.severity.to_u8(), flags])?;
+            if let Some(context) = &ann.context {
+                write_int(&mut record, string_table.idx(context)?)?;
+            }
+        }
+        write_int(&mut record, file.literals.len())?;
+        for lit in &file.literals {
+            write_int(&mut record, lit.range.offset)?;
+            write_int(&mut record, lit.range.end_offset)?;
+            write_int(&mut record, string_table.idx(&lit.kind)?)?;
+            write_int(&mut record, string_table.idx(&lit.value)?)?;
+        }
+        write_int(&mut record, file.metadata.len())?;
+        for entry in &file.metadata {
+            write_int(&mut record, entry.range.offset)?;
+            write_int(&mut record, entry.range.end_offset)?;
+            write_int(&mut record, string_table.idx(&entry.key)?)?;
+            write_int(&mut record, string_table.idx(&entry.value)?)?;
+        }
+        if !file.line_offsets.is_empty() {
+            write_int(&mut record, file.line_offsets.len())?;
+            for offset in &file.line_offsets {
+                record.write_all(&offset.to_le_bytes())?;
+            }
+        }
+        index.push(IndexEntry {
+            offset: writer.stream_position()? as usize,
+            len: record.len(),
+            checksum: record_checksum(&record),
+        });
+        writer.write_all(&record)?;
+    }
+    // Write string table offset
+    let string_table_offset = writer.stream_position()?;
+    writer.seek(SeekFrom::Start(string_table_offset_pos))?;
+    write_int(&mut writer, string_table_offset as usize)?;
+    writer.seek(SeekFrom::Start(string_table_offset))?;
+    // Write string table
+    string_table.write(&mut writer)?;
+    for entry in &index {
+        entry.write(&mut writer)?;
+    }
+    writer.write_all(&END_MARKER)?;
+    Ok(())
+}
+
+/// The fixed fields at the start of a segment.
+struct SegmentHeader {
+    version: u32,
+    string_table_offset: usize,
+    num_files: usize,
+    root_idx: Option<usize>,
+}
+
+fn read_segment_header<R: Read>(reader: &mut R) -> anyhow::Result<SegmentHeader> {
+    let mut magic = [0u8; 1];
+    reader.read_exact(&mut magic)?;
+    if magic[0] != MAGIC {
+        anyhow::bail!("invalid magic byte: expected 0xde, got {:x}", magic[0]);
+    }
+    let version = read_int(reader)? as u32;
+    if !SUPPORTED_ASSET_VERSIONS.contains(&version) {
+        anyhow::bail!(
+            "unsupported version: expected one of {:?}, got {}",
+            SUPPORTED_ASSET_VERSIONS,
+            version
+        );
+    }
+    let string_table_offset = read_int(reader)?;
+    let num_files = read_int(reader)?;
+    let root_idx = if version >= 5 { Some(read_int(reader)?) } else { None };
+    Ok(SegmentHeader { version, string_table_offset, num_files, root_idx })
+}
+
+/// Decodes a single segment, leaving `reader` positioned at the end of it.
+///
+/// In `recover` mode, a missing or unreadable string table is replaced by placeholders and
+/// decoding stops at the first truncated file record, returning the files read so far. With a
+/// `filter`, only the files whose path matches are returned, see `Project::decode_where`.
+fn decode_segment<R: Read + Seek>(
+    reader: &mut R,
+    options: &DecodeOptions,
+    recover: bool,
+    filter: Option<&dyn Fn(&str) -> bool>,
+) -> anyhow::Result<Vec<File>> {
+    let SegmentHeader { version, string_table_offset, num_files, root_idx } =
+        read_segment_header(reader)?;
+    let files_start = reader.stream_position()?;
+    // Read string table and file index
+    let mut index = None;
+    let string_table = if recover {
+        // An interrupted dump leaves the offset placeholder (0) in the header, or points at a
+        // table that was never completely written.
+        let table = if string_table_offset as u64 >= files_start {
+            reader.seek(SeekFrom::Start(string_table_offset as u64))?;
+            StringTable::read(&mut *reader).ok().inspect(|_| {
+                // Only the table itself matters here, a missing index or marker loses nothing.
+                if version >= 11 {
+                    let _ = (0..num_files).try_for_each(|_| IndexEntry::read(reader).map(drop));
+                }
+                if version >= 9 {
+                    let _ = reader.read_exact(&mut [0; END_MARKER.len()]);
+                }
+            })
+        } else {
+            None
+        };
+        table.unwrap_or_else(StringTable::placeholders)
+    } else {
+        reader.seek(SeekFrom::Start(string_table_offset as u64))?;
+        let table = StringTable::read(&mut *reader).context("truncated string table")?;
+        if version >= 11 {
+            let entries: anyhow::Result<Vec<_>> =
+                (0..num_files).map(|_| IndexEntry::read(reader)).collect();
+            index = Some(entries.context("truncated file index")?);
+        }
+        if version >= 9 {
+            let mut marker = [0; END_MARKER.len()];
+            if reader.read_exact(&mut marker).is_err() || marker != END_MARKER {
+                anyhow::bail!("missing end marker after the string table, the asset is truncated");
+            }
+        }
+        table
+    };
+    let root = match root_idx {
+        Some(_) if string_table.is_placeholder() => Cow::Borrowed(""),
+        Some(idx) if !options.relative_paths => string_table.get(idx)?,
+        _ => Cow::Borrowed(""),
+    };
+    let segment_end = reader.stream_position()?;
+    if let (Some(filter), Some(index)) = (filter, &index) {
+        let mut files = Vec::new();
+        for entry in index {
+            reader.seek(SeekFrom::Start(entry.offset as u64))?;
+            let path = format!("{root}{}", string_table.get(read_int(reader)?)?);
+            if !filter(&path) {
+                continue;
+            }
+            let mut record = vec![0; entry.len];
+            reader.seek(SeekFrom::Start(entry.offset as u64))?;
+            reader
+                .read_exact(&mut record)
+                .with_context(|| format!("truncated record of {path}"))?;
+            if record_checksum(&record) != entry.checksum {
+                anyhow::bail!("checksum mismatch in the record of {}", path);
+            }
+            let mut record = io::Cursor::new(record);
+            files.push(decode_file(&mut record, version, &string_table, &root, options)?);
+        }
+        reader.seek(SeekFrom::Start(segment_end))?;
+        return Ok(files);
+    }
+    // Read files
+    reader.seek(SeekFrom::Start(files_start))?;
+    let mut files = Vec::with_capacity(num_files);
+    for _ in 0..num_files {
+        match decode_file(reader, version, &string_table, &root, options) {
+            Ok(file) => files.push(file),
+            Err(_) if recover => {
+                reader.seek(SeekFrom::End(0))?;
+                return Ok(files);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if string_table.is_placeholder() {
+        // Whatever follows the file records is a partial table, so there are no further segments.
+        reader.seek(SeekFrom::End(0))?;
+        return Ok(files);
+    }
+    if let Some(filter) = filter {
+        files.retain(|file| filter(&file.path));
+    }
+    reader.seek(SeekFrom::Start(segment_end))?;
+    Ok(files)
+}
+
+/// Decodes a single file record of a segment with the given `version`.
+fn decode_file<R: Read + Seek>(
+    reader: &mut R,
+    version: u32,
+    string_table: &StringTable,
+    root: &str,
+    options: &DecodeOptions,
+) -> anyhow::Result<File> {
+    let path_idx = read_int(reader)?;
+    let content_idx = read_int(reader)?;
+    let flags = if version >= 3 { read_u32(reader)? } else { 0 };
+    let shebang_idx =
+        if version >= 7 && flags & FILE_FLAG_SHEBANG != 0 { Some(read_int(reader)?) } else { None };
+    let edition = if version >= 8 {
+        let byte = read_u8(reader)?;
+        Edition::iter()
+            .find(|&edition| edition as u8 == byte)
+            .ok_or_else(|| anyhow::anyhow!("invalid edition {}", byte))?
+    } else {
+        Edition::CURRENT
+    };
+    let num_nodes = read_int(reader)?;
+    let delta_offsets = version >= 12 && flags & FILE_FLAG_DELTA_OFFSETS != 0;
+    let hash_size = if flags & FILE_FLAG_SUBTREE_HASHES != 0 { 8 } else { 0 };
+    let mut tree: Vec<Node> = Vec::new();
+    if options.skip_trees && !delta_offsets {
+        skip_bytes(reader, num_nodes * (3 * INT_SIZE + hash_size))?;
+    } else if options.skip_trees {
+        for _ in 0..num_nodes {
+            for _ in 0..3 {
+                read_leb128(reader)?;
+            }
+            skip_bytes(reader, INT_SIZE + hash_size)?;
+        }
+    } else {
+        tree.reserve(num_nodes);
+        for _ in 0..num_nodes {
+            let (offset, end_offset) = if delta_offsets {
+                let distance = read_leb128(reader)? as usize;
+                let base = match distance {
+                    0 => 0,
+                    _ => tree
+                        .len()
+                        .checked_sub(distance)
+                        .map(|parent| tree[parent].range.offset)
+                        .ok_or_else(|| anyhow::anyhow!("invalid parent distance {}", distance))?,
+                };
+                let offset = base + read_leb128(reader)? as usize;
+                (offset, offset + read_leb128(reader)? as usize)
+            } else {
+                (read_int(reader)?, read_int(reader)?)
+            };
+            let type_idx = read_int(reader)?;
+            let subtree_hash = if hash_size != 0 { Some(read_u64(reader)?) } else { None };
+            tree.push(Node {
+                range: Range { offset, end_offset },
+                node_type: string_table.get(type_idx)?.into_owned(),
+                subtree_hash,
+            });
+        }
+    }
+    let num_errors = read_int(reader)?;
+    let severity_size = if version >= 6 { 1 } else { 0 };
+    let mut errors = Vec::new();
+    if options.skip_errors && version < 10 {
+        skip_bytes(reader, num_errors * (3 * INT_SIZE + severity_size))?;
+    } else if options.skip_errors {
+        // The flags byte decides whether a context index follows.
+        for _ in 0..num_errors {
+            skip_bytes(reader, 3 * INT_SIZE + severity_size)?;
+            let context = read_u8(reader)? & ANNOTATION_FLAG_CONTEXT != 0;
+            skip_bytes(reader, if context { INT_SIZE } else { 0 })?;
+        }
+    } else {
+        errors.reserve(num_errors);
+        for _ in 0..num_errors {
+            let offset = read_int(reader)?;
+            let end_offset = read_int(reader)?;
+            let text_idx = read_int(reader)?;
+            let severity =
+                if version >= 6 { Severity::from_u8(read_u8(reader)?)? } else { Severity::Error };
+            let flags = if version >= 10 { read_u8(reader)? } else { 0 };
+            let context_idx =
+                if flags & ANNOTATION_FLAG_CONTEXT != 0 { Some(read_int(reader)?) } else { None };
+            errors.push(Annotation {
+                range: Range { offset, end_offset },
+                text: string_table.get(text_idx)?.into_owned(),
+                severity,
+                context: context_idx
+                    .map(|idx| string_table.get(idx).map(Cow::into_owned))
+                    .transpose()?,
+            });
+        }
+    }
+    let mut literals = Vec::new();
+    if version >= 2 {
+        let num_literals = read_int(reader)?;
+        literals.reserve(num_literals);
+        for _ in 0..num_literals {
+            let offset = read_int(reader)?;
+            let end_offset = read_int(reader)?;
+            let kind_idx = read_int(reader)?;
+            let value_idx = read_int(reader)?;
+            literals.push(Literal {
+                range: Range { offset, end_offset },
+                kind: string_table.get(kind_idx)?.into_owned(),
+                value: string_table.get(value_idx)?.into_owned(),
+            });
+        }
+    }
+    let mut metadata = Vec::new();
+    if version >= 4 {
+        let num_entries = read_int(reader)?;
+        metadata.reserve(num_entries);
+        for _ in 0..num_entries {
+            let offset = read_int(reader)?;
+            let end_offset = read_int(reader)?;
+            let key_idx = read_int(reader)?;
+            let value_idx = read_int(reader)?;
+            metadata.push(Metadata {
+                range: Range { offset, end_offset },
+                key: string_table.get(key_idx)?.into_owned(),
+                value: string_table.get(value_idx)?.into_owned(),
+            });
+        }
+    }
+    let mut line_offsets = Vec::new();
+    if version >= 13 && flags & FILE_FLAG_LINE_OFFSETS != 0 {
+        let num_lines = read_int(reader)?;
+        line_offsets.reserve(num_lines);
+        for _ in 0..num_lines {
+            line_offsets.push(read_u32(reader)?);
+        }
+    }
+    Ok(File {
+        path: format!("{root}{}", string_table.get(path_idx)?),
+        content: string_table.get(content_idx)?.into_owned(),
+        tree,
+        errors,
+        literals,
+        metadata,
+        shebang: shebang_idx.map(|idx| string_table.get(idx).map(Cow::into_owned)).transpose()?,
+        edition,
+        line_offsets,
+    })
+}
+
+/// Computes the longest prefix of all `paths` that ends with a path separator.
+fn common_dir_prefix<'a>(mut paths: impl Iterator<Item = &'a str>) -> &'a str {
+    let Some(first) = paths.next() else { return "" };
+    let mut len = first.len();
+    for path in paths {
+        len = first.bytes().zip(path.bytes()).take(len).take_while(|(a, b)| a == b).count();
+    }
+    let dir_len = first[..len].rfind(['/', '\\']).map_or(0, |sep| sep + 1);
+    &first[..dir_len]
+}
+
+/// Hashes a length-prefixed string, so that adjacent fields cannot run into each other.
+fn hash_str(hasher: &mut Sha256, s: &str) {
+    hasher.update((s.len() as u64).to_le_bytes());
+    hasher.update(s);
+}
+
+fn hash_range(hasher: &mut Sha256, range: &Range) {
+    hasher.update((range.offset as u64).to_le_bytes());
+    hasher.update((range.end_offset as u64).to_le_bytes());
+}
+
+/// Computes the fields of each node record stored with `FILE_FLAG_DELTA_OFFSETS`: the distance
+/// back to the parent's record (0 for roots), the offset relative to the parent's offset
+/// (absolute for roots) and the length.
+fn delta_node_fields(file: &File) -> Vec<[u64; 3]> {
+    let parents = file.parents();
+    file.tree
+        .iter()
+        .zip(parents)
+        .enumerate()
+        .map(|(idx, (node, parent))| {
+            let (distance, base) = match parent {
+                Some(parent) => (idx - parent, file.tree[parent].range.offset),
+                None => (0, 0),
+            };
+            let len = node.range.end_offset.saturating_sub(node.range.offset);
+            [distance as u64, (node.range.offset - base) as u64, len as u64]
+        })
+        .collect()
+}
+
+fn leb128_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+fn write_leb128<W: Write>(w: &mut W, mut value: u64) -> anyhow::Result<()> {
+    while value >= 0x80 {
+        w.write_all(&[(value as u8) | 0x80])?;
+        value >>= 7;
+    }
+    w.write_all(&[value as u8])?;
+    Ok(())
+}
+
+fn read_leb128<R: Read>(r: &mut R) -> anyhow::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(r)?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    anyhow::bail!("LEB128 value does not fit in 64 bits")
+}
+
+/// Moves `r` forward by `len` bytes without reading them.
+fn skip_bytes<R: Seek>(r: &mut R, len: usize) -> anyhow::Result<()> {
+    if len > 0 {
+        r.seek(SeekFrom::Current(i64::try_from(len)?))?;
+    }
+    Ok(())
+}
+
+fn read_u8<R: Read>(r: &mut R) -> anyhow::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> anyhow::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> anyhow::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// The encoded size of an integer field in bytes.
+const INT_SIZE: usize = 4;
+
+fn read_int<R: Read>(r: &mut R) -> anyhow::Result<usize> {
+    Ok(read_u32(r)? as usize)
+}
+
+fn write_int<W: Write>(w: &mut W, value: usize) -> anyhow::Result<()> {
+    w.write_all(&u32::try_from(value)?.to_le_bytes())?;
+    Ok(())
+}
+
+/// Replaces every run of ASCII digits in `text` with `N`.
+fn strip_positions(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_digits = false;
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            if !in_digits {
+                stripped.push('N');
             }
+            in_digits = true;
+        } else {
+            stripped.push(c);
+            in_digits = false;
         }
-        // Write header
-        writer.write_all(&[MAGIC])?;
-        writer.write_all(&ASSET_ENCODING_VERSION.to_le_bytes())?;
-        let string_table_offset_pos = writer.stream_position()?;
-        writer.write_all(&0u32.to_le_bytes())?; // placeholder for string table offset
-        writer.write_all(&(self.files.len() as u32).to_le_bytes())?;
-        // Write files
-        for file in &self.files {
-            writer.write_all(&(string_table.idx(&file.path)? as u32).to_le_bytes())?;
-            writer.write_all(&(string_table.idx(&file.content)? as u32).to_le_bytes())?;
-            writer.write_all(&(file.tree.len() as u32).to_le_bytes())?;
-            for node in &file.tree {
-                writer.write_all(&(node.range.offset as u32).to_le_bytes())?;
-                writer.write_all(&(node.range.end_offset as u32).to_le_bytes())?;
-                writer.write_all(&(string_table.idx(&node.node_type)? as u32).to_le_bytes())?;
-            }
-            writer.write_all(&(file.errors.len() as u32).to_le_bytes())?;
-            for ann in &file.errors {
-                writer.write_all(&(ann.range.offset as u32).to_le_bytes())?;
-                writer.write_all(&(ann.range.end_offset as u32).to_le_bytes())?;
-                writer.write_all(&(string_table.idx(&ann.text)? as u32).to_le_bytes())?;
-            }
-        }
-        // Write string table offset
-        let string_table_offset = writer.stream_position()? as u32;
-        let cur = writer.stream_position()?;
-        writer.seek(SeekFrom::Start(string_table_offset_pos))?;
-        writer.write_all(&string_table_offset.to_le_bytes())?;
-        writer.seek(SeekFrom::Start(cur))?;
-        // Write string table
-        string_table.write(&mut writer)?;
-        Ok(())
     }
+    stripped
+}
 
-    pub fn decode<R: Read + Seek>(mut reader: R) -> anyhow::Result<Self> {
-        let mut magic = [0u8; 1];
-        reader.read_exact(&mut magic)?;
-        if magic[0] != MAGIC {
-            anyhow::bail!("invalid magic byte: expected 0xde, got {:x}", magic[0]);
-        }
-        let version = read_u32(&mut reader)?;
-        if version != ASSET_ENCODING_VERSION {
-            anyhow::bail!("version mismatch: expected {}, got {}", ASSET_ENCODING_VERSION, version);
-        }
-        let string_table_offset = read_u32(&mut reader)?;
-        let num_files = read_u32(&mut reader)?;
-        let files_start = reader.stream_position()?;
-        // Read string table
-        reader.seek(SeekFrom::Start(string_table_offset as u64))?;
-        let string_table = StringTable::read(&mut reader)?;
-        // Read files
-        reader.seek(SeekFrom::Start(files_start))?;
-        let mut files = Vec::with_capacity(num_files as usize);
-        for _ in 0..num_files {
-            let path_idx = read_u32(&mut reader)? as usize;
-            let content_idx = read_u32(&mut reader)? as usize;
-            let num_nodes = read_u32(&mut reader)?;
-            let mut tree = Vec::with_capacity(num_nodes as usize);
-            for _ in 0..num_nodes {
-                let offset = read_u32(&mut reader)? as usize;
-                let end_offset = read_u32(&mut reader)? as usize;
-                let type_idx = read_u32(&mut reader)? as usize;
-                tree.push(Node {
-                    range: Range { offset, end_offset },
-                    node_type: string_table.get(type_idx)?.to_owned(),
-                });
-            }
-            let num_errors = read_u32(&mut reader)?;
-            let mut errors = Vec::with_capacity(num_errors as usize);
-            for _ in 0..num_errors {
-                let offset = read_u32(&mut reader)? as usize;
-                let end_offset = read_u32(&mut reader)? as usize;
-                let text_idx = read_u32(&mut reader)? as usize;
-                errors.push(Annotation {
-                    range: Range { offset, end_offset },
-                    text: string_table.get(text_idx)?.to_owned(),
-                });
-            }
-            files.push(File {
-                path: string_table.get(path_idx)?.to_owned(),
-                content: string_table.get(content_idx)?.to_owned(),
-                tree,
-                errors,
-            });
-        }
-        Ok(Project { files })
+/// The location and checksum of a file record, stored after the string table since version 11.
+struct IndexEntry {
+    /// The offset of the record from the start of the asset.
+    offset: usize,
+    /// The length of the record in bytes.
+    len: usize,
+    /// See `record_checksum`.
+    checksum: u64,
+}
+
+impl IndexEntry {
+    /// The encoded size of an entry in bytes.
+    const SIZE: usize = 2 * INT_SIZE + 8;
+
+    fn read<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+        Ok(Self { offset: read_int(r)?, len: read_int(r)?, checksum: read_u64(r)? })
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> anyhow::Result<()> {
+        write_int(w, self.offset)?;
+        write_int(w, self.len)?;
+        w.write_all(&self.checksum.to_le_bytes())?;
+        Ok(())
     }
 }
 
-fn read_u32<R: Read>(r: &mut R) -> anyhow::Result<u32> {
-    let mut buf = [0u8; 4];
-    r.read_exact(&mut buf)?;
-    Ok(u32::from_le_bytes(buf))
+/// The first 8 bytes of the SHA-256 of an encoded file record, little-endian.
+fn record_checksum(record: &[u8]) -> u64 {
+    u64::from_le_bytes(Sha256::digest(record)[..8].try_into().unwrap())
 }
 
 #[derive(Default)]
 struct StringTableBuilder {
-    map: HashMap<String, usize>,
+    map: FxHashMap<String, usize>,
     vec: Vec<String>,
 }
 
@@ -184,9 +2288,9 @@ impl StringTableBuilder {
         self.map.get(s).copied().ok_or_else(|| anyhow::anyhow!("string not found in table: {}", s))
     }
     fn write<W: Write>(&self, mut w: W) -> anyhow::Result<()> {
-        w.write_all(&(self.vec.len() as u32).to_le_bytes())?;
+        write_int(&mut w, self.vec.len())?;
         for s in &self.vec {
-            w.write_all(&(s.len() as u32).to_le_bytes())?;
+            write_int(&mut w, s.len())?;
             w.write_all(s.as_bytes())?;
         }
         Ok(())
@@ -195,33 +2299,54 @@ impl StringTableBuilder {
 
 struct StringTable {
     vec: Vec<String>,
+    /// Set when the table could not be read and `get` hands out placeholders instead.
+    placeholder: bool,
 }
 
 impl StringTable {
     fn read<R: Read>(mut r: R) -> anyhow::Result<Self> {
-        let num_strings = read_u32(&mut r)?;
-        let mut vec = Vec::with_capacity(num_strings as usize);
+        let num_strings = read_int(&mut r)?;
+        let mut vec = Vec::with_capacity(num_strings);
         for _ in 0..num_strings {
-            let len = read_u32(&mut r)? as usize;
+            let len = read_int(&mut r)?;
             let mut buf = vec![0u8; len];
             r.read_exact(&mut buf)?;
             vec.push(String::from_utf8(buf)?);
         }
-        Ok(Self { vec })
+        Ok(Self { vec, placeholder: false })
+    }
+    /// A table standing in for one that is missing, resolving every index to `<string:idx>`.
+    fn placeholders() -> Self {
+        Self { vec: Vec::new(), placeholder: true }
     }
-    fn get(&self, idx: usize) -> anyhow::Result<&str> {
-        self.vec.get(idx).map(|s| s.as_str()).ok_or_else(|| anyhow::anyhow!("string index {} out of range", idx))
+    fn is_placeholder(&self) -> bool {
+        self.placeholder
+    }
+    fn get(&self, idx: usize) -> anyhow::Result<Cow<'_, str>> {
+        if self.placeholder {
+            return Ok(Cow::Owned(format!("<string:{idx}>")));
+        }
+        self.vec
+            .get(idx)
+            .map(|s| Cow::Borrowed(s.as_str()))
+            .ok_or_else(|| anyhow::anyhow!("string index {} out of range", idx))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::asset_gen::{
+        ParseOptions, parse_rust_to_asset_file, parse_rust_to_asset_file_with_options,
+    };
     use std::io::Cursor;
 
-    #[test]
-    fn encode_decode_roundtrip() {
-        let project = Project {
+    fn parse(text: &str) -> File {
+        parse_rust_to_asset_file("lib.rs".to_owned(), text.to_owned())
+    }
+
+    fn sample_project() -> Project {
+        Project {
             files: vec![
                 File {
                     path: "foo.rs".to_string(),
@@ -229,11 +2354,15 @@ mod tests {
                     tree: vec![Node {
                         range: Range { offset: 0, end_offset: 10 },
                         node_type: "Function".to_string(),
+                        subtree_hash: Some(0x1234_5678_9abc_def0),
                     }],
                     errors: vec![Annotation {
                         range: Range { offset: 3, end_offset: 7 },
                         text: "error: something".to_string(),
+                        severity: Severity::Error,
+                        context: None,
                     }],
+                    ..Default::default()
                 },
                 File {
                     path: "bar.rs".to_string(),
@@ -241,11 +2370,15 @@ mod tests {
                     tree: vec![Node {
                         range: Range { offset: 0, end_offset: 10 },
                         node_type: "Let".to_string(),
+                        subtree_hash: None,
                     }],
                     errors: vec![Annotation {
                         range: Range { offset: 4, end_offset: 5 },
                         text: "warning: unused variable".to_string(),
+                        severity: Severity::Warning,
+                        context: None,
                     }],
+                    ..Default::default()
                 },
                 File {
                     path: "baz.rs".to_string(),
@@ -253,18 +2386,1024 @@ mod tests {
                     tree: vec![Node {
                         range: Range { offset: 0, end_offset: 8 },
                         node_type: "Struct".to_string(),
+                        subtree_hash: None,
                     }],
                     errors: vec![Annotation {
                         range: Range { offset: 0, end_offset: 6 },
                         text: "note: struct defined here".to_string(),
+                        severity: Severity::Note,
+                        context: None,
+                    }],
+                    literals: vec![Literal {
+                        range: Range { offset: 0, end_offset: 6 },
+                        kind: "STRING".to_owned(),
+                        value: "a\nb".to_owned(),
+                    }],
+                    metadata: vec![Metadata {
+                        range: Range { offset: 0, end_offset: 9 },
+                        key: METADATA_DERIVE.to_owned(),
+                        value: "Debug".to_owned(),
                     }],
+                    ..Default::default()
                 },
             ],
-        };
+        }
+    }
+
+    impl Project {
+        /// Encodes the project twice and checks that both encodings are byte for byte equal.
+        fn encode_is_deterministic(&self) -> bool {
+            let encode = || {
+                let mut buf = Cursor::new(Vec::new());
+                self.encode(&mut buf).expect("encode");
+                buf.into_inner()
+            };
+            encode() == encode()
+        }
+    }
+
+    fn roundtrip(project: &Project) -> Project {
         let mut buf = Cursor::new(Vec::new());
         project.encode(&mut buf).expect("encode");
         buf.set_position(0);
-        let decoded = Project::decode(&mut buf).expect("decode");
-        assert_eq!(project, decoded);
+        Project::decode(&mut buf).expect("decode")
+    }
+
+    #[test]
+    fn encode_is_deterministic() {
+        let all = ParseOptions {
+            capture_literals: true,
+            subtree_hashes: true,
+            capture_derives: true,
+            capture_macro_calls: true,
+            flag_unsafe: true,
+            capture_trivia: true,
+            capture_tests: true,
+            ..Default::default()
+        };
+        let text = r#"
+#[derive(Debug, Clone)]
+struct S { s: &'static str }
+#[test]
+fn t() { let s = S { s: "a\tb" }; println!("{s:?}"); unsafe { std::hint::unreachable_unchecked() } }
+fn broken( {
+"#;
+        let parsed = |options: &ParseOptions| Project {
+            files: vec![
+                parse_rust_to_asset_file_with_options(
+                    "a/x.rs".to_owned(),
+                    text.to_owned(),
+                    options,
+                ),
+                parse_rust_to_asset_file_with_options(
+                    "a/b/y.rs".to_owned(),
+                    text.to_owned(),
+                    options,
+                ),
+            ],
+        };
+        let anonymized = ParseOptions { anonymize: true, ..all.clone() };
+        let outline = ParseOptions { outline: true, ..all.clone() };
+        let fixtures = [
+            Project { files: Vec::new() },
+            sample_project(),
+            parsed(&ParseOptions::default()),
+            parsed(&all),
+            parsed(&anonymized),
+            parsed(&outline),
+        ];
+        for fixture in &fixtures {
+            assert!(fixture.encode_is_deterministic());
+        }
+        // Parsing the same sources again yields the same bytes, too.
+        let encode = |project: Project| {
+            let mut buf = Cursor::new(Vec::new());
+            project.encode(&mut buf).unwrap();
+            buf.into_inner()
+        };
+        assert_eq!(encode(parsed(&all)), encode(parsed(&all)));
+    }
+
+    #[test]
+    fn encoded_size_matches_encoding() {
+        let mut project = sample_project();
+        project.files.push(parse("#!/bin/sh\nfn f( {}\nfn g() { \"s\" }"));
+        project.files[3].path = "dir/sub/lib.rs".to_owned();
+        let delta = EncodeOptions { delta_offsets: true };
+        for project in [Project { files: Vec::new() }, project] {
+            for options in [EncodeOptions::default(), delta.clone()] {
+                let encoded = project.encode_to_vec(&options).unwrap();
+                assert_eq!(project.encoded_size(&options), encoded.len(), "{options:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn delta_offsets_roundtrip_smaller() {
+        let depth = 200;
+        let content = format!("fn f() {}{}", "{ ".repeat(depth), " }".repeat(depth));
+        let mut project = Project { files: vec![parse(&content), parse("fn g( {}")] };
+        project.files[1].tree.iter_mut().for_each(|node| node.subtree_hash = Some(7));
+        let delta = EncodeOptions { delta_offsets: true };
+        let encoded = project.encode_to_vec(&delta).unwrap();
+        assert_eq!(project.encoded_size(&delta), encoded.len());
+        assert_eq!(Project::decode(Cursor::new(&encoded)).unwrap(), project);
+        let absolute = project.section_sizes(&EncodeOptions::default()).nodes;
+        let compact = project.section_sizes(&delta).nodes;
+        assert!(compact < absolute, "{compact} vs {absolute}");
+        assert!(encoded.len() < project.encoded_size(&EncodeOptions::default()));
+
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buf = Vec::new();
+            write_leb128(&mut buf, value).unwrap();
+            assert_eq!(buf.len(), leb128_len(value));
+            assert_eq!(read_leb128(&mut &buf[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn section_sizes_sum_to_encoded_size() {
+        let mut project = sample_project();
+        project.files.push(parse("#!/bin/sh\nfn f( {}\nfn g() { \"s\" }"));
+        project.files[3].line_offsets = project.files[3].compute_line_offsets();
+        for options in [EncodeOptions::default(), EncodeOptions { delta_offsets: true }] {
+            let sizes = project.section_sizes(&options);
+            let encoded = project.encode_to_vec(&options).unwrap();
+            assert_eq!(sizes.total(), encoded.len(), "{options:?}");
+            assert!(sizes.nodes > 0 && sizes.errors > 0, "{options:?}");
+        }
+        let sizes = project.section_sizes(&EncodeOptions::default());
+        let encoded = project.encode_to_vec(&EncodeOptions::default()).unwrap();
+        let table_offset = u32::from_le_bytes(encoded[5..9].try_into().unwrap()) as usize;
+        let table_end = encoded.len() - sizes.index - END_MARKER.len();
+        assert_eq!(sizes.string_table, table_end - table_offset);
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let project = sample_project();
+        assert_eq!(project, roundtrip(&project));
+    }
+
+    #[test]
+    fn current_version_is_supported() {
+        assert_eq!(Project::supported_versions().last(), Some(&CURRENT_ASSET_VERSION));
+        let mut buf = Cursor::new(Vec::new());
+        sample_project().encode(&mut buf).unwrap();
+        let mut buf = buf.into_inner();
+        assert_eq!(u32::from_le_bytes(buf[1..5].try_into().unwrap()), CURRENT_ASSET_VERSION);
+        buf[1..5].copy_from_slice(&(CURRENT_ASSET_VERSION + 1).to_le_bytes());
+        assert!(Project::decode(Cursor::new(buf)).is_err());
+    }
+
+    #[test]
+    fn missing_modules() {
+        let file =
+            |path: &str, text: &str| parse_rust_to_asset_file(path.to_owned(), text.to_owned());
+        let project = Project {
+            files: vec![
+                file(
+                    "src/lib.rs",
+                    concat!(
+                        "mod present;\nmod nested;\nmod missing;\n",
+                        "#[path = \"x.rs\"]\nmod elsewhere;\n",
+                        "mod inline { mod inner; fn f() {} }",
+                    ),
+                ),
+                file("src/present.rs", "mod child;"),
+                file("src/present/child.rs", ""),
+                file("src/nested/mod.rs", "mod gone;"),
+                file("src/inline/inner.rs", ""),
+            ],
+        };
+        assert_eq!(
+            project.missing_modules(),
+            vec![
+                ("src/lib.rs".to_owned(), "missing".to_owned()),
+                ("src/nested/mod.rs".to_owned(), "gone".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shebang_is_captured() {
+        let file = parse("#!/usr/bin/env rust-script\nfn main() {}\n");
+        assert_eq!(file.shebang.as_deref(), Some("#!/usr/bin/env rust-script"));
+        assert!(file.errors.is_empty());
+        assert_eq!(file.item_signatures()[0].1, "fn main()");
+        let project = Project { files: vec![file, parse("#![allow(unused)]\nfn main() {}")] };
+        assert_eq!(project.files[1].shebang, None);
+        assert_eq!(roundtrip(&project), project);
+    }
+
+    #[cfg(feature = "sarif")]
+    #[test]
+    fn to_sarif() {
+        let mut broken = parse("fn f() {}\nfn g( { \"ä\" }");
+        broken.errors.push(Annotation {
+            range: Range { offset: 0, end_offset: 2 },
+            text: "unused".to_owned(),
+            severity: Severity::Warning,
+            context: None,
+        });
+        broken.errors.push(Annotation {
+            range: Range { offset: 0, end_offset: 2 },
+            text: "note".to_owned(),
+            severity: Severity::Note,
+            context: None,
+        });
+        let project = Project { files: vec![broken] };
+        let mut out = Vec::new();
+        project.to_sarif(&mut out).unwrap();
+        let log: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+        let results = log["runs"][0]["results"].as_array().unwrap();
+        let levels: Vec<_> =
+            results.iter().map(|result| result["level"].as_str().unwrap()).collect();
+        assert_eq!(levels.iter().filter(|&&level| level == "warning").count(), 1);
+        assert!(levels[..levels.len() - 1].iter().all(|&level| level == "error"));
+        let error = &results[0];
+        assert_eq!(error["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "lib.rs");
+        assert_eq!(error["message"]["text"], project.files[0].errors[0].text);
+        let region = &error["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startLine"], 2);
+        let offset = project.files[0].errors[0].range.offset;
+        assert_eq!(region["startColumn"], offset - "fn f() {}\n".len() + 1);
+    }
+
+    #[test]
+    fn item_signatures() {
+        let file = parse(
+            r#"
+fn f(x: i32) -> i32 { x }
+struct S;
+struct T<U> where U: Copy { u: U }
+impl T<i32> {
+    fn new() -> Self { todo!() }
+}
+"#,
+        );
+        let signatures: Vec<_> = file.item_signatures().into_iter().map(|(_, text)| text).collect();
+        assert_eq!(
+            signatures,
+            [
+                "fn f(x: i32) -> i32",
+                "struct S",
+                "struct T<U> where U: Copy",
+                "impl T<i32>",
+                "fn new() -> Self"
+            ]
+        );
+        let (range, _) = &file.item_signatures()[0];
+        assert_eq!(*range, Range { offset: 1, end_offset: 20 });
+    }
+
+    #[test]
+    fn node_size_breakdown() {
+        let file = parse(
+            r#"
+fn small() {}
+fn large(x: i32) -> i32 {
+    let y = x * 2 + 1;
+    if y > 10 { y - 10 } else { y }
+}
+"#,
+        );
+        let breakdown = file.node_size_breakdown();
+        assert_eq!(breakdown.len(), 2);
+        let (small, large) = (&breakdown[0], &breakdown[1]);
+        assert!(file.content[small.0.offset..small.0.end_offset].starts_with("fn small"));
+        assert!(file.content[large.0.offset..large.0.end_offset].starts_with("fn large"));
+        assert!(large.1 > small.1 * 3, "{breakdown:?}");
+        let total: usize = breakdown.iter().map(|(_, count)| count + 1).sum();
+        assert_eq!(total, file.tree.len() - 1);
+    }
+
+    #[test]
+    fn chunks_by_item() {
+        let content = r#"
+/// Doc comment of a.
+fn a() {}
+
+// Detached comment, chunked with b.
+
+fn b() {
+    a();
+}
+fn c() {}
+// Trailing comment.
+"#;
+        let file = parse(content);
+        let chunks: Vec<&str> = file
+            .chunks_by_item()
+            .iter()
+            .map(|chunk| &file.content[chunk.offset..chunk.end_offset])
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                "/// Doc comment of a.\nfn a() {}",
+                "// Detached comment, chunked with b.\n\nfn b() {\n    a();\n}",
+                "fn c() {}\n// Trailing comment.",
+            ]
+        );
+        let significant = |text: &str| text.split_whitespace().collect::<String>();
+        assert_eq!(significant(&chunks.concat()), significant(content));
+    }
+
+    #[test]
+    fn into_iter_yields_owned_files() {
+        let project = sample_project();
+        let borrowed: Vec<&str> = (&project).into_iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(borrowed, ["foo.rs", "bar.rs", "baz.rs"]);
+
+        let moved = Project {
+            files: project
+                .clone()
+                .into_iter()
+                .map(|mut file| {
+                    file.path = format!("src/{}", file.path);
+                    file.errors.clear();
+                    file
+                })
+                .collect(),
+        };
+        assert_eq!(moved.files.len(), project.files.len());
+        for (moved, original) in moved.files.iter().zip(&project.files) {
+            assert_eq!(moved.path, format!("src/{}", original.path));
+            assert!(moved.errors.is_empty());
+            assert_eq!(moved.tree, original.tree);
+        }
+        assert_eq!(roundtrip(&moved), moved);
+    }
+
+    #[test]
+    fn validate_kinds() {
+        let mut project = Project { files: vec![parse("fn f() {}"), parse("struct S;")] };
+        assert_eq!(project.validate_kinds(), []);
+        project.files[1].path = "bad.rs".to_owned();
+        project.files[1].tree[2].node_type = "STRUKT".to_owned();
+        project.files[1].tree.push(Node {
+            range: Range { offset: 0, end_offset: 0 },
+            node_type: "<string:7>".to_owned(),
+            subtree_hash: None,
+        });
+        let last = project.files[1].tree.len() - 1;
+        assert_eq!(
+            project.validate_kinds(),
+            [
+                ("bad.rs".to_owned(), 2, "STRUKT".to_owned()),
+                ("bad.rs".to_owned(), last, "<string:7>".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_kinds() {
+        let mut project =
+            Project { files: vec![parse("fn f() {}\nfn g(x: u8) {}"), parse("struct S;")] };
+        let original = project.clone();
+        let map: FxHashMap<String, String> =
+            [("FN", "FUNCTION"), ("PARAM_LIST", "FN"), ("UNKNOWN", "X")]
+                .into_iter()
+                .map(|(from, to)| (from.to_owned(), to.to_owned()))
+                .collect();
+        project.rename_kinds(&map);
+        let kinds = |project: &Project, kind: &str| {
+            project
+                .files
+                .iter()
+                .flat_map(|file| &file.tree)
+                .filter(|node| node.node_type == kind)
+                .count()
+        };
+        assert_eq!(kinds(&project, "FUNCTION"), kinds(&original, "FN"));
+        assert_eq!(kinds(&project, "FN"), kinds(&original, "PARAM_LIST"));
+        assert_eq!(kinds(&project, "FUNCTION"), 2);
+        assert_eq!(kinds(&project, "PARAM_LIST"), 0);
+        assert_eq!(project.files[1], original.files[1]);
+    }
+
+    #[test]
+    fn filter_nodes_removes_nodes_and_ancestry_follows_containment() {
+        let mut project = Project { files: vec![parse("fn f(x: i32) -> i32 { x + g(x) }")] };
+        let original = project.files[0].clone();
+        let removed = ["PATH", "PATH_SEGMENT", "ARG_LIST", "PARAM_LIST"];
+        let keep = |node: &Node| !removed.contains(&node.node_type.as_str());
+        project.filter_nodes(keep);
+
+        let parents = original.parents();
+        let nearest_kept = |mut idx: Option<usize>| {
+            while let Some(i) = idx.filter(|&i| !keep(&original.tree[i])) {
+                idx = parents[i];
+            }
+            idx.map(|i| &original.tree[i])
+        };
+        let expected: Vec<_> = (0..original.tree.len())
+            .filter(|&idx| keep(&original.tree[idx]))
+            .map(|idx| (&original.tree[idx], nearest_kept(parents[idx])))
+            .collect();
+        let file = &project.files[0];
+        let actual: Vec<_> = file
+            .parents()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, parent)| (&file.tree[idx], parent.map(|parent| &file.tree[parent])))
+            .collect();
+        assert_eq!(actual, expected);
+        assert!(file.tree.iter().all(keep));
+        assert!(file.tree.len() < original.tree.len());
+    }
+
+    #[test]
+    fn line_offsets() {
+        let content = "fn f() {}\n\nstruct S;\r\nconst C: () = ();\n";
+        let options = ParseOptions { capture_line_offsets: true, ..Default::default() };
+        let file =
+            parse_rust_to_asset_file_with_options("a.rs".to_owned(), content.to_owned(), &options);
+        assert_eq!(file.line_offsets, [0, 10, 11, 22, 40]);
+        assert_eq!(file.line_offsets, file.compute_line_offsets());
+
+        let project = roundtrip(&Project { files: vec![file.clone(), parse(content)] });
+        assert_eq!(project.files[0], file);
+        assert!(project.files[1].line_offsets.is_empty());
+        for file in &project.files {
+            let line =
+                |idx| file.line_range(idx).map(|range| &content[range.offset..range.end_offset]);
+            assert_eq!(line(2), Some("struct S;\r\n"));
+            assert_eq!(line(3), Some("const C: () = ();\n"));
+            assert_eq!(line(4), Some(""));
+            assert_eq!(line(5), None);
+        }
+    }
+
+    #[test]
+    fn node_line_span() {
+        let content = "fn f() {\n    let x = 1;\n\n    x;\n}\nstruct S;\n";
+        let mut file = parse(content);
+        let span = |file: &File, kind: &str| {
+            let node = file.tree.iter().find(|node| node.node_type == kind).unwrap();
+            file.node_line_span(node)
+        };
+        assert_eq!(span(&file, "FN"), 5);
+        assert_eq!(span(&file, "STRUCT"), 1);
+
+        let options = ParseOptions { capture_line_spans: true, ..Default::default() };
+        let captured =
+            parse_rust_to_asset_file_with_options(String::new(), content.to_owned(), &options);
+        assert_eq!(captured.metadata_values(&captured.tree[1].range, METADATA_LINE_SPAN), ["5"]);
+        file.metadata = captured.metadata;
+        file.content.clear();
+        assert_eq!(span(&file, "FN"), 5);
+    }
+
+    #[test]
+    fn offset_index_agrees_with_linear_scan() {
+        let file = parse(
+            r#"
+mod m {
+    struct S { a: u32, b: (u8, u8) }
+    fn f(x: i32) -> i32 { let y = x + 1; if y > 0 { y } else { -y } }
+}
+fn broken( { 1 +
+"#,
+        );
+        let index = file.build_offset_index();
+        for offset in 0..=file.content.len() + 1 {
+            assert_eq!(index.node_at_offset(offset), file.node_at_offset(offset), "at {offset}");
+        }
+        assert_eq!(file.node_at_offset(0).unwrap().node_type, "SOURCE_FILE");
+        let offset = file.content.find("a: u32").unwrap();
+        assert_eq!(index.node_at_offset(offset).unwrap().node_type, "NAME");
+    }
+
+    #[test]
+    fn decode_recover_truncated_asset() {
+        let project = sample_project();
+        let mut buf = Cursor::new(Vec::new());
+        project.encode(&mut buf).unwrap();
+        let buf = buf.into_inner();
+        let table_offset = u32::from_le_bytes(buf[5..9].try_into().unwrap()) as usize;
+
+        // Cut in the middle of the string table, and before it with the offset never patched.
+        let mut truncated = buf[..table_offset + 6].to_vec();
+        let mut unpatched = buf[..table_offset].to_vec();
+        unpatched[5..9].copy_from_slice(&0u32.to_le_bytes());
+        for buf in [truncated.clone(), unpatched] {
+            assert!(Project::decode(Cursor::new(&buf)).is_err());
+            let recovered = Project::decode_recover(Cursor::new(&buf)).unwrap();
+            assert_eq!(recovered.files.len(), project.files.len());
+            for (recovered, original) in recovered.files.iter().zip(&project.files) {
+                assert!(recovered.path.starts_with("<string:"), "{}", recovered.path);
+                assert!(recovered.content.starts_with("<string:"));
+                assert_eq!(recovered.tree.len(), original.tree.len());
+                assert_eq!(recovered.errors.len(), original.errors.len());
+                let ranges =
+                    |file: &File| file.tree.iter().map(|n| n.range.clone()).collect::<Vec<_>>();
+                assert_eq!(ranges(recovered), ranges(original));
+                let severities =
+                    |file: &File| file.errors.iter().map(|a| a.severity).collect::<Vec<_>>();
+                assert_eq!(severities(recovered), severities(original));
+            }
+        }
+
+        // A record cut off mid-way is dropped, the ones before it survive.
+        truncated.truncate(table_offset - 2);
+        let recovered = Project::decode_recover(Cursor::new(&truncated)).unwrap();
+        assert_eq!(recovered.files.len(), project.files.len() - 1);
+
+        assert_eq!(Project::decode_recover(Cursor::new(&buf)).unwrap(), project);
+
+        // Junk or a cut off segment after a valid one keeps the files of the valid one.
+        let mut junk = buf.clone();
+        junk.extend_from_slice(&[0x12, 0x34, 0x56, 0x78, 0x9a]);
+        assert_eq!(Project::decode_recover(Cursor::new(&junk)).unwrap(), project);
+        let mut cut_off = buf.clone();
+        cut_off.extend_from_slice(&buf[..20]);
+        assert_eq!(Project::decode_recover(Cursor::new(&cut_off)).unwrap(), project);
+    }
+
+    #[test]
+    fn truncated_string_table_is_detected() {
+        let mut buf = Cursor::new(Vec::new());
+        sample_project().encode(&mut buf).unwrap();
+        let buf = buf.into_inner();
+        let table_offset = u32::from_le_bytes(buf[5..9].try_into().unwrap()) as usize;
+        for len in [table_offset + 6, buf.len() - 2, buf.len() - 1] {
+            let err = Project::decode(Cursor::new(&buf[..len])).unwrap_err().to_string();
+            assert!(err.contains("truncated"), "{len}: {err}");
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored_unless_rejected() {
+        let project = sample_project();
+        let encoded = project.encode_to_vec(&EncodeOptions::default()).unwrap();
+        let reject = DecodeOptions { reject_trailing_bytes: true, ..Default::default() };
+        let mut bogus_version = vec![MAGIC];
+        bogus_version.extend_from_slice(&[0xff; 16]);
+        for garbage in [&b"garbage"[..], &[MAGIC, 1, 0], &bogus_version] {
+            let mut buf = encoded.clone();
+            buf.extend_from_slice(garbage);
+            assert_eq!(Project::decode(Cursor::new(&buf)).unwrap(), project);
+            let err = Project::decode_with_options(Cursor::new(&buf), &reject).unwrap_err();
+            let expected = format!("{} trailing bytes", garbage.len());
+            assert!(err.to_string().contains(&expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn decode_skips_trees_and_errors() {
+        let mut project = sample_project();
+        project.files[1].errors[0].context = Some("x".to_owned());
+        project.files[2].tree.push(Node {
+            range: Range { offset: 7, end_offset: 8 },
+            node_type: "Semi".to_owned(),
+            subtree_hash: None,
+        });
+        for delta_offsets in [false, true] {
+            let encoded = project.encode_to_vec(&EncodeOptions { delta_offsets }).unwrap();
+            let decode = |skip_trees, skip_errors| {
+                let options = DecodeOptions { skip_trees, skip_errors, ..Default::default() };
+                Project::decode_with_options(Cursor::new(&encoded), &options).unwrap()
+            };
+            let full = decode(false, false);
+            assert!(full.files.iter().all(|file| !file.tree.is_empty()));
+            for (skip_trees, skip_errors) in [(true, false), (false, true), (true, true)] {
+                let mut expected = full.clone();
+                for file in &mut expected.files {
+                    if skip_trees {
+                        file.tree.clear();
+                    }
+                    if skip_errors {
+                        file.errors.clear();
+                    }
+                }
+                assert_eq!(decode(skip_trees, skip_errors), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn multi_project_decodes_by_name() {
+        let mut sample = sample_project();
+        sample.files.push(parse("fn f() {}"));
+        let multi = MultiProject {
+            projects: [("core", sample), ("cli", Project { files: vec![parse("struct S;")] })]
+                .into_iter()
+                .map(|(name, project)| (name.to_owned(), project))
+                .collect(),
+        };
+        let mut buf = Cursor::new(vec![0xaa]);
+        buf.set_position(1);
+        multi.encode(&mut buf).unwrap();
+        let mut encoded = buf.into_inner();
+        let asset = |encoded: &[u8]| {
+            let mut reader = Cursor::new(encoded.to_vec());
+            reader.set_position(1);
+            reader
+        };
+        let core = Some(multi.projects["core"].clone());
+        assert_eq!(MultiProject::project_names(asset(&encoded)).unwrap(), ["cli", "core"]);
+        assert_eq!(MultiProject::decode(asset(&encoded)).unwrap(), multi);
+        assert_eq!(MultiProject::decode_project(asset(&encoded), "core").unwrap(), core);
+        assert_eq!(MultiProject::decode_project(asset(&encoded), "missing").unwrap(), None);
+        assert!(Project::decode(asset(&encoded)).is_err());
+
+        // Only the selected project is read, so corrupting another one does not matter.
+        let size = |name: &str| multi.projects[name].encoded_size(&EncodeOptions::default());
+        let cli_start = encoded.len() - size("core") - size("cli");
+        encoded[cli_start] = 0;
+        assert!(MultiProject::decode_project(asset(&encoded), "cli").is_err());
+        assert_eq!(MultiProject::decode_project(asset(&encoded), "core").unwrap(), core);
+    }
+
+    #[test]
+    fn identify_asset() {
+        let project = sample_project();
+        let encoded = project.encode_to_vec(&EncodeOptions::default()).unwrap();
+        let info = Project::identify(Cursor::new(&encoded)).unwrap();
+        assert_eq!(
+            info,
+            AssetInfo {
+                valid_magic: true,
+                version: CURRENT_ASSET_VERSION,
+                supported: true,
+                num_files: 3,
+                delta_offsets: false,
+                has_index: true,
+                size: encoded.len() as u64,
+            }
+        );
+        let delta = EncodeOptions { delta_offsets: true };
+        let encoded = project.encode_to_vec(&delta).unwrap();
+        assert!(Project::identify(Cursor::new(&encoded)).unwrap().delta_offsets);
+        // Without a file record there is no flag to read the layout from.
+        let empty = Project { files: Vec::new() }.encode_to_vec(&delta).unwrap();
+        assert!(!Project::identify(Cursor::new(&empty)).unwrap().delta_offsets);
+
+        let mut future = encoded.clone();
+        future[1..5].copy_from_slice(&999u32.to_le_bytes());
+        let info = Project::identify(Cursor::new(&future)).unwrap();
+        assert_eq!((info.valid_magic, info.version, info.supported), (true, 999, false));
+        let info = Project::identify(Cursor::new(b"not an asset")).unwrap();
+        assert_eq!(info, AssetInfo { size: 12, ..Default::default() });
+    }
+
+    #[test]
+    fn read_string_table_without_decoding() {
+        let project = sample_project();
+        let buf = project.encode_to_vec(&EncodeOptions::default()).unwrap();
+        let strings = Project::read_string_table(Cursor::new(&buf)).unwrap();
+        let unique: FxHashSet<&String> = strings.iter().collect();
+        assert_eq!(unique.len(), strings.len());
+        let root = segment_root(&project.files);
+        let expected = segment_string_table(&project.files, root);
+        assert_eq!(strings, expected.vec);
+        for file in &project.files {
+            assert!(strings.contains(&file.content));
+            assert!(file.tree.iter().all(|node| strings.contains(&node.node_type)));
+        }
+
+        let mut appended = Cursor::new(buf);
+        Project::append_segment(&mut appended, &project.files[..1]).unwrap();
+        appended.set_position(0);
+        let strings = Project::read_string_table(appended).unwrap();
+        assert!(strings.len() > expected.vec.len());
+        assert_eq!(strings[..expected.vec.len()], expected.vec);
+    }
+
+    #[test]
+    fn decode_where_only_reads_matching_files() {
+        let mut files = Vec::new();
+        for path in ["src/lib.rs", "tests/it.rs", "src/a/mod.rs", "build.rs"] {
+            let mut file = parse("fn f( {}");
+            file.path = format!("/proj/{path}");
+            files.push(file);
+        }
+        let project = Project { files };
+        let mut buf = Cursor::new(project.encode_to_vec(&EncodeOptions::default()).unwrap());
+        Project::append_segment(&mut buf, &project.files[2..]).unwrap();
+        let mut buf = buf.into_inner();
+        let in_src = |path: &str| path.starts_with("/proj/src/");
+        let decoded = Project::decode_where(Cursor::new(&buf), in_src).unwrap();
+        let expected: Vec<_> = [0, 2, 2].iter().map(|&idx| project.files[idx].clone()).collect();
+        assert_eq!(decoded.files, expected);
+
+        // Corrupting an unmatched record goes unnoticed, a matched one fails the checksum.
+        let header_len = 1 + 4 * 4;
+        buf[header_len + 4] ^= 1;
+        assert!(Project::decode_where(Cursor::new(&buf), |path| path.ends_with("it.rs")).is_ok());
+        let err = Project::decode_where(Cursor::new(&buf), in_src).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{err}");
+    }
+
+    #[test]
+    fn append_segments() {
+        let project = sample_project();
+        let mut buf = Cursor::new(Vec::new());
+        for file in &project.files {
+            Project::append_segment(&mut buf, std::slice::from_ref(file)).expect("append");
+            // Appending must not depend on where the writer is positioned.
+            buf.set_position(0);
+        }
+        buf.set_position(0);
+        assert_eq!(Project::decode(&mut buf).expect("decode"), project);
+
+        let mut buf = Cursor::new(Vec::new());
+        Project { files: project.files[..2].to_vec() }.encode(&mut buf).expect("encode");
+        Project::append_segment(&mut buf, &project.files[2..]).expect("append");
+        Project::append_segment(&mut buf, &[]).expect("append");
+        buf.set_position(0);
+        assert_eq!(Project::decode(&mut buf).expect("decode"), project);
+    }
+
+    #[test]
+    fn reencode_v1_asset() {
+        // Version 1 layout: no root, flags, literals, metadata, severities or end marker.
+        let strings = ["src/main.rs", "fn f() {}", "SOURCE_FILE", "FN", "unused"];
+        let header_len = 1 + 3 * 4;
+        let file_len = 13 * 4;
+        let mut buf = vec![MAGIC];
+        let int = |buf: &mut Vec<u8>, v: u32| buf.extend_from_slice(&v.to_le_bytes());
+        for v in [1, header_len + file_len, 1] {
+            int(&mut buf, v);
+        }
+        for v in [0, 1, 2, 0, 9, 2, 0, 9, 3, 1, 3, 4, 4] {
+            int(&mut buf, v);
+        }
+        assert_eq!(buf.len() as u32, header_len + file_len);
+        int(&mut buf, strings.len() as u32);
+        for s in strings {
+            int(&mut buf, s.len() as u32);
+            buf.extend_from_slice(s.as_bytes());
+        }
+
+        let project = Project::decode(Cursor::new(&buf)).unwrap();
+        let reencoded = project.encode_to_vec(&EncodeOptions::default()).unwrap();
+        assert_eq!(u32::from_le_bytes(reencoded[1..5].try_into().unwrap()), CURRENT_ASSET_VERSION);
+        assert_eq!(reencoded[reencoded.len() - 2..], END_MARKER);
+        let decoded = Project::decode(Cursor::new(&reencoded)).unwrap();
+        assert_eq!(decoded, project);
+        let info = Project::identify(Cursor::new(&reencoded)).unwrap();
+        assert!(info.has_index);
+        assert_eq!(Project::decode_where(Cursor::new(&reencoded), |_| true).unwrap(), project);
+        let mut corrupted = reencoded.clone();
+        corrupted[1 + 4 * 4 + 4] ^= 1;
+        let err = Project::decode_where(Cursor::new(&corrupted), |_| true).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{err}");
+        let file = &decoded.files[0];
+        assert_eq!(file.path, "src/main.rs");
+        assert_eq!(file.tree.len(), 2);
+        assert_eq!(file.errors[0].text, "unused");
+        assert_eq!(file.errors[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn paths_are_stored_relative_to_common_root() {
+        let root = "/home/user/work/projects/deeply/nested/crate/";
+        let project = Project {
+            files: ["src/lib.rs", "src/a/mod.rs", "tests/it.rs"]
+                .into_iter()
+                .map(|path| File { path: format!("{root}{path}"), ..Default::default() })
+                .collect(),
+        };
+        let mut buf = Cursor::new(Vec::new());
+        project.encode(&mut buf).expect("encode");
+        let bytes = buf.into_inner();
+        let occurrences = bytes.windows(root.len()).filter(|w| *w == root.as_bytes()).count();
+        assert_eq!(occurrences, 1);
+
+        assert_eq!(Project::decode(Cursor::new(&bytes)).expect("decode"), project);
+        let options = DecodeOptions { relative_paths: true, ..Default::default() };
+        let relative = Project::decode_with_options(Cursor::new(&bytes), &options).expect("decode");
+        let paths: Vec<_> = relative.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["src/lib.rs", "src/a/mod.rs", "tests/it.rs"]);
+
+        assert_eq!(common_dir_prefix(["a/b.rs", "c.rs"].into_iter()), "");
+        assert_eq!(common_dir_prefix(["src/ab.rs", "src/ac.rs"].into_iter()), "src/");
+        assert_eq!(common_dir_prefix(["src/lib.rs"].into_iter()), "src/");
+    }
+
+    #[test]
+    fn all_annotations_and_errors() {
+        let mut project = sample_project();
+        project.files.push(parse("fn f( {}\nfn g() -> { 1 }"));
+        let all: Vec<_> =
+            project.all_annotations().map(|(path, ann)| (path, ann.severity)).collect();
+        assert_eq!(all.len(), 3 + project.files[3].errors.len());
+        assert_eq!(
+            all[..3],
+            [
+                ("foo.rs", Severity::Error),
+                ("bar.rs", Severity::Warning),
+                ("baz.rs", Severity::Note)
+            ]
+        );
+
+        let errors: Vec<_> = project.all_errors().map(|(path, _)| path).collect();
+        assert!(errors.len() > 2);
+        assert_eq!(errors[0], "foo.rs");
+        assert!(errors[1..].iter().all(|path| *path == "lib.rs"));
+    }
+
+    #[test]
+    fn rebase_paths() {
+        let mut project = Project {
+            files: ["/home/a/src/lib.rs", "/home/a/src2/lib.rs", "/opt/b/main.rs", "/home/a/src"]
+                .into_iter()
+                .map(|path| File { path: path.to_owned(), ..Default::default() })
+                .collect(),
+        };
+        assert_eq!(project.rebase_paths("/home/a/src", "/work/src"), 2);
+        let paths: Vec<_> = project.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/work/src/lib.rs", "/home/a/src2/lib.rs", "/opt/b/main.rs", "/work/src"]
+        );
+    }
+
+    #[test]
+    fn files_matching_glob() {
+        let project = roundtrip(&Project {
+            files: ["src/lib.rs", "src/a/b.rs", "src/a/b.txt", "tests/t.rs", "/abs/src/c.rs"]
+                .into_iter()
+                .map(|path| File { path: path.to_owned(), ..Default::default() })
+                .collect(),
+        });
+        let matching = |pattern| -> Vec<_> {
+            project.files_matching(pattern).unwrap().iter().map(|file| file.path.as_str()).collect()
+        };
+        assert_eq!(matching("src/**/*.rs"), ["src/lib.rs", "src/a/b.rs"]);
+        assert_eq!(matching("src/*.rs"), ["src/lib.rs"]);
+        assert_eq!(matching("**/src/**/*.rs"), ["src/lib.rs", "src/a/b.rs", "/abs/src/c.rs"]);
+        assert!(matching("*.md").is_empty());
+        assert!(project.files_matching("src/[").is_err());
+    }
+
+    #[test]
+    fn retain_and_remove_paths() {
+        let set = |paths: &[&str]| paths.iter().map(|path| (*path).to_owned()).collect();
+        let paths = |project: &Project| -> Vec<_> {
+            project.files.iter().map(|file| file.path.clone()).collect()
+        };
+        let project = sample_project();
+
+        let mut retained = project.clone();
+        retained.retain_paths(&set(&["baz.rs", "foo.rs", "missing.rs"]));
+        assert_eq!(paths(&retained), ["foo.rs", "baz.rs"]);
+        retained.retain_paths(&set(&["missing.rs"]));
+        assert!(retained.files.is_empty());
+
+        let mut removed = project.clone();
+        removed.remove_paths(&set(&["bar.rs", "missing.rs"]));
+        assert_eq!(paths(&removed), ["foo.rs", "baz.rs"]);
+        removed.remove_paths(&set(&["missing.rs"]));
+        assert_eq!(paths(&removed), ["foo.rs", "baz.rs"]);
+    }
+
+    #[test]
+    fn content_digest_ignores_file_order() {
+        let project = sample_project();
+        let mut reversed = project.clone();
+        reversed.files.reverse();
+        assert_eq!(project.content_digest(), reversed.content_digest());
+        assert_eq!(project.content_digest(), roundtrip(&project).content_digest());
+
+        let mut changed = project.clone();
+        changed.files[1].content.push(' ');
+        assert_ne!(project.content_digest(), changed.content_digest());
+    }
+
+    #[test]
+    fn merge_annotations_by_path() {
+        let mut project = roundtrip(&sample_project());
+        let lint = |offset, text: &str| Annotation {
+            range: Range { offset, end_offset: offset + 1 },
+            text: text.to_owned(),
+            severity: Severity::Warning,
+            context: None,
+        };
+        let existing = project.files[0].errors[0].clone();
+        let mut extra = FxHashMap::default();
+        extra.insert(
+            "foo.rs".to_owned(),
+            vec![lint(0, "warning: first"), existing.clone(), lint(5, "warning: second")],
+        );
+        extra.insert("missing.rs".to_owned(), vec![lint(0, "warning: dropped")]);
+        project.merge_annotations(extra, true);
+
+        assert_eq!(
+            project.files[0].errors,
+            vec![existing, lint(0, "warning: first"), lint(5, "warning: second")]
+        );
+        assert_eq!(project.files[1].errors.len(), 1);
+        assert_eq!(project.files[2].errors.len(), 1);
+    }
+
+    #[test]
+    fn merge_content_from_content_only_project() {
+        let complete = sample_project();
+        let mut trees = complete.clone();
+        for file in &mut trees.files {
+            file.content.clear();
+        }
+        let mut contents = complete.clone();
+        for file in &mut contents.files {
+            file.tree.clear();
+            file.errors.clear();
+        }
+        trees.files[2].path = "only_trees.rs".to_owned();
+        let unmatched = trees.merge_content(&contents);
+        assert_eq!(unmatched, ["only_trees.rs", complete.files[2].path.as_str()]);
+        assert_eq!(trees.files[..2], complete.files[..2]);
+        assert!(trees.files[2].content.is_empty());
+    }
+
+    #[test]
+    fn error_message_histogram() {
+        let mut project = Project {
+            files: vec![parse("fn f( {}"), parse("fn g( {}\nfn h( {}"), parse("let x = 1;")],
+        };
+        let histogram = project.error_message_histogram();
+        let expected: BTreeMap<String, usize> =
+            [("expected R_PAREN", 3), ("expected an item", 1), ("expected value parameter", 3)]
+                .into_iter()
+                .map(|(text, count)| (text.to_owned(), count))
+                .collect();
+        assert_eq!(histogram, expected);
+        assert!(Project { files: vec![parse("fn f() {}")] }.error_message_histogram().is_empty());
+
+        // Warnings and notes are not counted.
+        let annotation = |text: &str, severity| Annotation {
+            range: Range { offset: 0, end_offset: 1 },
+            text: text.to_owned(),
+            severity,
+            context: None,
+        };
+        project.files[0].errors.push(annotation("expected R_PAREN", Severity::Note));
+        project.files[1].errors.push(annotation("unused", Severity::Warning));
+        assert_eq!(project.error_message_histogram(), expected);
+
+        project.files[0].errors.push(annotation("unexpected token at 3:5", Severity::Error));
+        project.files[2].errors.push(annotation("unexpected token at 10:2", Severity::Error));
+        let histogram = project.normalized_error_message_histogram();
+        assert_eq!(histogram["unexpected token at N:N"], 2);
+        assert_eq!(histogram["expected R_PAREN"], 3);
+        assert_eq!(project.error_message_histogram()["unexpected token at 3:5"], 1);
+    }
+
+    #[test]
+    fn write_annotations_plain() {
+        let mut broken = parse("fn f() {}\nfn ä( {}");
+        broken.path = "b.rs".to_owned();
+        broken.errors.insert(
+            0,
+            Annotation {
+                range: Range { offset: 17, end_offset: 18 },
+                text: "late".to_owned(),
+                severity: Severity::Note,
+                context: None,
+            },
+        );
+        let mut first = parse("fn g() {}");
+        first.path = "a.rs".to_owned();
+        first.errors.push(Annotation {
+            range: Range { offset: 3, end_offset: 4 },
+            text: "unused".to_owned(),
+            severity: Severity::Warning,
+            context: None,
+        });
+        let project = Project { files: vec![broken, first] };
+        let mut out = Vec::new();
+        project.write_annotations_plain(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.rs:1:4: warning: unused\n\
+             b.rs:2:6: error: expected value parameter\n\
+             b.rs:2:6: error: expected R_PAREN\n\
+             b.rs:2:7: note: late\n"
+        );
+    }
+
+    #[test]
+    fn sort_files() {
+        let mut project = Project {
+            files: vec![parse("fn a() {}"), parse("fn b( {}"), parse("fn c( {} fn d( {}")],
+        };
+        for (file, name) in project.files.iter_mut().zip(["a.rs", "b.rs", "c.rs"]) {
+            file.path = name.to_owned();
+        }
+        project.files.push(File { path: "empty.rs".to_owned(), ..Default::default() });
+        let paths = |project: &Project| -> Vec<String> {
+            project.files.iter().map(|file| file.path.clone()).collect()
+        };
+        project.sort_by_error_count_desc();
+        assert_eq!(paths(&project), ["c.rs", "b.rs", "a.rs", "empty.rs"]);
+        project.sort_by_node_count_desc();
+        assert_eq!(paths(&project)[0], "c.rs");
+        assert_eq!(paths(&project)[3], "empty.rs");
+        project.sort_files_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(paths(&project), ["a.rs", "b.rs", "c.rs", "empty.rs"]);
     }
-}
\ No newline at end of file
+}
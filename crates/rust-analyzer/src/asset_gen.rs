@@ -1,50 +1,1937 @@
-use crate::asset::{File, Node, Range, Annotation};
-use syntax::{SourceFile, SyntaxNode, TextRange, Edition, WalkEvent};
+use crate::asset::{
+    Annotation, File, Literal, METADATA_BOM_STRIPPED, METADATA_DERIVE, METADATA_FIELD,
+    METADATA_GENERIC, METADATA_IMPL_TARGET, METADATA_IMPL_TRAIT, METADATA_IMPORT,
+    METADATA_INITIALIZER_NODES, METADATA_LINE_SPAN, METADATA_MACRO_CALL, METADATA_MODULE_DOC,
+    METADATA_PARAM, METADATA_RETURN, METADATA_TEST, METADATA_TOKEN, METADATA_TRAIT_ITEM,
+    METADATA_TRIVIA, METADATA_VARIANT, METADATA_VISIBILITY, Metadata, Node, Project, Range,
+    Severity, TraitItem, TraitItemKind, TriviaAssociation, Visibility,
+};
+use rustc_hash::FxHashMap;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use syntax::{
+    AstNode, AstToken, Edition, NodeOrToken, SourceFile, SyntaxKind, SyntaxNode, SyntaxToken,
+    TextRange, TextSize, WalkEvent,
+    ast::{self, HasAttrs, HasName, HasVisibility},
+    match_ast,
+};
+use tenthash::TentHash;
 
-/// Flattens the syntax tree into a list of Nodes (preorder traversal).
-fn flatten_ast(node: &SyntaxNode) -> Vec<Node> {
+/// The default `File::error_node_ratio` above which a file is considered not to be Rust at all.
+pub const DEFAULT_UNPARSEABLE_THRESHOLD: f64 = 0.3;
+
+/// The text of the warnings added by `ParseOptions::flag_unresolved_macros`, followed by the path
+/// of the macro and `!`.
+const UNRESOLVED_MACRO_MESSAGE: &str = "unresolved macro invocation: ";
+
+/// Controls what `parse_rust_to_asset_file_with_options` captures besides the tree and errors.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Capture the unescaped values of string, byte string and char literals.
+    pub capture_literals: bool,
+    /// Compute a structural hash for every node, see `Node::subtree_hash`.
+    pub subtree_hashes: bool,
+    /// Capture the derive macros applied to structs, enums and unions, including those inside
+    /// `cfg_attr`, as `METADATA_DERIVE` metadata.
+    pub capture_derives: bool,
+    /// Capture the names of the generic parameters of every item as `METADATA_GENERIC` metadata
+    /// on the item's range.
+    pub capture_generics: bool,
+    /// Capture the declared visibility of every item that can have one as `METADATA_VISIBILITY`
+    /// metadata on the item's range, see `File::visibility`.
+    pub capture_visibility: bool,
+    /// Capture the text of the parameters and of the return type of every function as
+    /// `METADATA_PARAM` and `METADATA_RETURN` metadata on its range, see `File::signature`.
+    pub capture_signatures: bool,
+    /// Capture the trait and self type of every impl block as `METADATA_IMPL_TRAIT` and
+    /// `METADATA_IMPL_TARGET` metadata on its range, see `File::impl_info`.
+    pub capture_impls: bool,
+    /// Capture the paths imported by every `use` item as `METADATA_IMPORT` metadata on its range,
+    /// see `File::imports`.
+    pub capture_imports: bool,
+    /// Capture the inner doc comments before the first item of the file as `METADATA_MODULE_DOC`
+    /// metadata, see `File::module_doc`.
+    pub capture_module_doc: bool,
+    /// Capture the fields of every struct and the variants of every enum as `METADATA_FIELD` and
+    /// `METADATA_VARIANT` metadata on its range, see `File::struct_fields` and
+    /// `File::enum_variants`.
+    pub capture_fields: bool,
+    /// Capture the associated functions, types and consts declared in every trait as
+    /// `METADATA_TRAIT_ITEM` metadata on its range, see `File::trait_items`.
+    pub capture_trait_items: bool,
+    /// Capture the number of nodes in the initializer of every `const` and `static` item as
+    /// `METADATA_INITIALIZER_NODES` metadata on its range, see `File::initializer_nodes`.
+    pub capture_initializer_sizes: bool,
+    /// Capture the path of every macro call as `METADATA_MACRO_CALL` metadata on the range of its
+    /// arguments.
+    pub capture_macro_calls: bool,
+    /// Mark unsafe functions, blocks, impls and traits with a `Severity::Note` annotation with
+    /// the text `unsafe`.
+    pub flag_unsafe: bool,
+    /// Add a `Severity::Warning` annotation with the text `unresolved macro invocation: name!`
+    /// on every macro call the parser had to recover from, because it is not followed by
+    /// delimited arguments. The parse errors themselves are kept.
+    pub flag_unresolved_macros: bool,
+    /// Only store an outline of the file: its items and the items directly nested in modules,
+    /// impls and traits, each followed by its `NAME` node if it has one.
+    ///
+    /// The file is still parsed in full and its parse errors are kept, but the `capture_*` and
+    /// `flag_*` passes are skipped, which saves their walks over the tree. Line offsets and
+    /// `anonymize` do not depend on the tree shape and still apply.
+    pub outline: bool,
+    /// Drop the content, shebang and annotation contexts and replace the text of identifier and
+    /// literal tokens by stable pseudonyms (`var_1`, `lit_1`, ...), stored as `METADATA_TOKEN`
+    /// metadata on each token.
+    ///
+    /// Equal texts get equal pseudonyms within a file. Literal values, the derive and macro call
+    /// metadata and the macro paths in `flag_unresolved_macros` warnings are pseudonymized the
+    /// same way, while the tree is kept unchanged. Signature, impl, import, module doc, field,
+    /// variant and trait item metadata is dropped.
+    pub anonymize: bool,
+    /// Tag every comment and whitespace token with its `TriviaAssociation`, stored as
+    /// `METADATA_TRIVIA` metadata on the token's range.
+    pub capture_trivia: bool,
+    /// Capture the name of every function with a `#[test]`-like attribute, one whose path ends
+    /// in `test` such as `#[tokio::test]`, as `METADATA_TEST` metadata on the function's range.
+    pub capture_tests: bool,
+    /// Store the offset at which each line starts in `File::line_offsets`.
+    pub capture_line_offsets: bool,
+    /// Store the number of lines every item spans as `METADATA_LINE_SPAN` metadata, see
+    /// `File::node_line_span`.
+    pub capture_line_spans: bool,
+    /// Remove a leading UTF-8 byte order mark from the content before parsing, so that offsets
+    /// match tools which ignore it, and record that with `METADATA_BOM_STRIPPED` metadata.
+    pub strip_bom: bool,
+    /// The edition to parse under, `Edition::CURRENT` if unset.
+    pub edition: Option<Edition>,
+    /// Attach the source text of the erroring region, truncated to at most this many bytes, to
+    /// every `Severity::Error` annotation as its `context`.
+    ///
+    /// Most parse errors are reported at an empty range, for those the text of the first
+    /// non-trivia token at or after the error is used instead.
+    pub error_context: Option<usize>,
+}
+
+/// Flattens the syntax tree into a list of Nodes in canonical order, see `canonical_order`.
+fn flatten_ast(node: &SyntaxNode, with_hashes: bool) -> Vec<Node> {
     let mut nodes = Vec::new();
-    for event in node.preorder_with_tokens() {
-        if let WalkEvent::Enter(n) = event {
-            if let Some(n) = n.as_node() {
-                let kind = format!("{:?}", n.kind());
-                let range = n.text_range();
-                nodes.push(Node {
-                    range: Range {
-                        offset: range.start().into(),
-                        end_offset: range.end().into(),
-                    },
-                    node_type: kind,
-                });
+    let mut depth = 0;
+    for event in node.preorder() {
+        match event {
+            WalkEvent::Enter(n) => {
+                nodes.push((node_from_syntax(&n), depth));
+                depth += 1;
             }
+            WalkEvent::Leave(_) => depth -= 1,
         }
     }
+    if with_hashes {
+        let mut hashes = Vec::with_capacity(nodes.len());
+        subtree_hashes(node, &mut hashes);
+        for ((node, _), hash) in nodes.iter_mut().zip(hashes) {
+            node.subtree_hash = Some(hash);
+        }
+    }
+    canonical_order(nodes)
+}
+
+/// Puts preorder `(node, depth)` pairs into the canonical order of `File::tree`: preorder, except
+/// that every run of consecutive nodes with the same range is ordered by depth, then kind, then
+/// original position.
+///
+/// Such runs are chains of nodes wrapping a single child, or empty nodes at the same offset, whose
+/// relative order in the parser's output may change between parser versions for error recovery
+/// cases; sorting them keeps the range-based queries on `File` deterministic.
+fn canonical_order(mut nodes: Vec<(Node, usize)>) -> Vec<Node> {
+    let mut start = 0;
+    while start < nodes.len() {
+        let range = nodes[start].0.range.clone();
+        let len = nodes[start..].iter().take_while(|(node, _)| node.range == range).count();
+        // The sort is stable, so nodes of equal depth and kind keep their original order.
+        nodes[start..start + len].sort_by(|(a, a_depth), (b, b_depth)| {
+            (a_depth, &a.node_type).cmp(&(b_depth, &b.node_type))
+        });
+        start += len;
+    }
+    nodes.into_iter().map(|(node, _)| node).collect()
+}
+
+fn node_from_syntax(node: &SyntaxNode) -> Node {
+    Node {
+        range: range_from_text_range(node.text_range()),
+        node_type: format!("{:?}", node.kind()),
+        subtree_hash: None,
+    }
+}
+
+/// Flattens the outline of the file (see `ParseOptions::outline`) into a list of Nodes.
+fn flatten_outline(root: &SyntaxNode, with_hashes: bool) -> Vec<Node> {
+    fn item(node: &SyntaxNode, nested: bool, with_hashes: bool, nodes: &mut Vec<Node>) {
+        if !is_outline_item(node.kind()) {
+            return;
+        }
+        let mut push = |node: &SyntaxNode| {
+            let mut flat = node_from_syntax(node);
+            if with_hashes {
+                flat.subtree_hash = Some(subtree_hashes(node, &mut Vec::new()));
+            }
+            nodes.push(flat);
+        };
+        push(node);
+        if let Some(name) = node.children().find(|child| child.kind() == SyntaxKind::NAME) {
+            push(&name);
+        }
+        if nested {
+            return;
+        }
+        for list in node.children().filter(|child| {
+            matches!(child.kind(), SyntaxKind::ITEM_LIST | SyntaxKind::ASSOC_ITEM_LIST)
+        }) {
+            for child in list.children() {
+                item(&child, true, with_hashes, nodes);
+            }
+        }
+    }
+
+    let mut nodes = Vec::new();
+    for child in root.children() {
+        item(&child, false, with_hashes, &mut nodes);
+    }
     nodes
 }
 
+fn is_outline_item(kind: SyntaxKind) -> bool {
+    matches!(
+        kind,
+        SyntaxKind::CONST
+            | SyntaxKind::ENUM
+            | SyntaxKind::FN
+            | SyntaxKind::IMPL
+            | SyntaxKind::MACRO_DEF
+            | SyntaxKind::MACRO_RULES
+            | SyntaxKind::MODULE
+            | SyntaxKind::STATIC
+            | SyntaxKind::STRUCT
+            | SyntaxKind::TRAIT
+            | SyntaxKind::TYPE_ALIAS
+            | SyntaxKind::UNION
+    )
+}
+
+/// Computes the subtree hashes of `node` and its descendants, pushing them in preorder.
+///
+/// A node's hash covers its kind, its length, and the offsets relative to it and hashes of its
+/// children, so it does not depend on the node's position in the file or on token text.
+fn subtree_hashes(node: &SyntaxNode, hashes: &mut Vec<u64>) -> u64 {
+    let idx = hashes.len();
+    hashes.push(0);
+    let range = node.text_range();
+    let mut hasher = TentHash::new();
+    hasher.update(format!("{:?}", node.kind()));
+    hasher.update(u32::from(range.len()).to_le_bytes());
+    for child in node.children() {
+        let child_hash = subtree_hashes(&child, hashes);
+        hasher.update(u32::from(child.text_range().start() - range.start()).to_le_bytes());
+        hasher.update(child_hash.to_le_bytes());
+    }
+    let digest = hasher.finalize();
+    let hash = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    hashes[idx] = hash;
+    hash
+}
+
+/// Collects the decoded values of all literal tokens, skipping those with invalid escapes.
+fn collect_literals(node: &SyntaxNode) -> Vec<Literal> {
+    node.descendants_with_tokens()
+        .filter_map(|it| it.into_token())
+        .filter_map(|token| {
+            let value = literal_value(&token)?;
+            Some(Literal {
+                range: range_from_text_range(token.text_range()),
+                kind: format!("{:?}", token.kind()),
+                value,
+            })
+        })
+        .collect()
+}
+
+/// Unescapes a literal token. Byte literals are converted lossily, as the value is stored as text.
+fn literal_value(token: &SyntaxToken) -> Option<String> {
+    let value = match token.kind() {
+        SyntaxKind::STRING => ast::String::cast(token.clone())?.value().ok()?.into_owned(),
+        SyntaxKind::BYTE_STRING => {
+            String::from_utf8_lossy(&ast::ByteString::cast(token.clone())?.value().ok()?)
+                .into_owned()
+        }
+        SyntaxKind::C_STRING => {
+            String::from_utf8_lossy(&ast::CString::cast(token.clone())?.value().ok()?).into_owned()
+        }
+        SyntaxKind::CHAR => ast::Char::cast(token.clone())?.value().ok()?.to_string(),
+        SyntaxKind::BYTE => char::from(ast::Byte::cast(token.clone())?.value().ok()?).to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Records the association of every trivia token using a line-based rule: trivia preceded by
+/// code on the same line is trailing, otherwise it is leading unless a blank line or the end of
+/// the file separates it from the next code.
+fn collect_trivia(root: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    let tokens: Vec<SyntaxToken> =
+        root.descendants_with_tokens().filter_map(|it| it.into_token()).collect();
+    let text = root.text().to_string();
+    let start = |token: &SyntaxToken| usize::from(token.text_range().start());
+    let end = |token: &SyntaxToken| usize::from(token.text_range().end());
+    let mut prev_code_end: Option<usize> = None;
+    for (idx, token) in tokens.iter().enumerate() {
+        if !token.kind().is_trivia() {
+            prev_code_end = Some(end(token));
+            continue;
+        }
+        let trailing = prev_code_end.is_some_and(|prev| !text[prev..start(token)].contains('\n'));
+        let association = if trailing {
+            TriviaAssociation::Trailing
+        } else {
+            let next_code = tokens[idx + 1..].iter().find(|it| !it.kind().is_trivia());
+            match next_code {
+                Some(next) if text[start(token)..start(next)].matches('\n').count() < 2 => {
+                    TriviaAssociation::Leading
+                }
+                _ => TriviaAssociation::Standalone,
+            }
+        };
+        metadata.push(Metadata {
+            range: range_from_text_range(token.text_range()),
+            key: METADATA_TRIVIA.to_owned(),
+            value: association.as_str().to_owned(),
+        });
+    }
+}
+
+/// Records the derive macro names of every ADT as metadata on the ADT's range.
+fn collect_derives(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for adt in node.descendants().filter_map(ast::Adt::cast) {
+        let range = range_from_text_range(adt.syntax().text_range());
+        for attr in adt.attrs() {
+            let Some((name, tt)) = attr.as_simple_call() else { continue };
+            let mut names = Vec::new();
+            match name.as_str() {
+                "derive" => names.extend(comma_separated(&tt)),
+                "cfg_attr" => derives_in_cfg_attr(&tt, &mut names),
+                _ => continue,
+            }
+            metadata.extend(names.into_iter().map(|value| Metadata {
+                range: range.clone(),
+                key: METADATA_DERIVE.to_owned(),
+                value,
+            }));
+        }
+    }
+}
+
+/// Annotates every macro call without delimited arguments, see
+/// `ParseOptions::flag_unresolved_macros`.
+fn collect_unresolved_macros(node: &SyntaxNode, errors: &mut Vec<Annotation>) {
+    for call in node.descendants().filter_map(ast::MacroCall::cast) {
+        let Some(path) = call.path().filter(|_| call.token_tree().is_none()) else { continue };
+        errors.push(Annotation {
+            range: range_from_text_range(call.syntax().text_range()),
+            text: format!("{UNRESOLVED_MACRO_MESSAGE}{path}!"),
+            severity: Severity::Warning,
+            context: None,
+        });
+    }
+}
+
+/// Annotates every unsafe function, block, impl and trait.
+fn collect_unsafe(node: &SyntaxNode, errors: &mut Vec<Annotation>) {
+    for node in node.descendants() {
+        let is_unsafe = match_ast! {
+            match node {
+                ast::Fn(it) => it.unsafe_token().is_some(),
+                ast::BlockExpr(it) => it.unsafe_token().is_some(),
+                ast::Impl(it) => it.unsafe_token().is_some(),
+                ast::Trait(it) => it.unsafe_token().is_some(),
+                _ => false,
+            }
+        };
+        if is_unsafe {
+            errors.push(Annotation {
+                range: range_from_text_range(node.text_range()),
+                text: "unsafe".to_owned(),
+                severity: Severity::Note,
+                context: None,
+            });
+        }
+    }
+}
+
+/// Records the path of every macro call as metadata on the range of its arguments.
+fn collect_macro_calls(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for call in node.descendants().filter_map(ast::MacroCall::cast) {
+        let (Some(path), Some(tt)) = (call.path(), call.token_tree()) else { continue };
+        metadata.push(Metadata {
+            range: range_from_text_range(tt.syntax().text_range()),
+            key: METADATA_MACRO_CALL.to_owned(),
+            value: path.syntax().text().to_string(),
+        });
+    }
+}
+
+/// Records the visibility of every item except impls and `macro_rules!`, which have none. This
+/// includes `use` and `extern crate` items, whose visibility makes them re-exports.
+fn collect_visibility(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for item in node.descendants().filter(|node| {
+        let kind = node.kind();
+        (is_outline_item(kind) || matches!(kind, SyntaxKind::USE | SyntaxKind::EXTERN_CRATE))
+            && !matches!(kind, SyntaxKind::IMPL | SyntaxKind::MACRO_RULES)
+    }) {
+        let Some(item) = ast::AnyHasVisibility::cast(item) else { continue };
+        let visibility = match item.visibility().map(|vis| vis.kind()) {
+            None | Some(ast::VisibilityKind::PubSelf) => Visibility::Private,
+            Some(ast::VisibilityKind::Pub) => Visibility::Public,
+            Some(ast::VisibilityKind::PubCrate) => Visibility::Crate,
+            Some(ast::VisibilityKind::PubSuper) => Visibility::Super,
+            Some(ast::VisibilityKind::In(path)) => {
+                Visibility::Restricted(path.syntax().text().to_string())
+            }
+        };
+        metadata.push(Metadata {
+            range: range_from_text_range(item.syntax().text_range()),
+            key: METADATA_VISIBILITY.to_owned(),
+            value: visibility.to_metadata_value(),
+        });
+    }
+}
+
+/// Records the parameters and the return type of every function.
+fn collect_signatures(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for func in node.descendants().filter_map(ast::Fn::cast) {
+        let range = range_from_text_range(func.syntax().text_range());
+        let params = func.param_list().into_iter().flat_map(|list| {
+            list.syntax()
+                .children()
+                .filter(|param| matches!(param.kind(), SyntaxKind::SELF_PARAM | SyntaxKind::PARAM))
+                .collect::<Vec<_>>()
+        });
+        for param in params {
+            metadata.push(Metadata {
+                range: range.clone(),
+                key: METADATA_PARAM.to_owned(),
+                value: param.text().to_string(),
+            });
+        }
+        let ret = func.ret_type().and_then(|ret| ret.ty());
+        metadata.push(Metadata {
+            range,
+            key: METADATA_RETURN.to_owned(),
+            value: ret.map(|ty| ty.syntax().text().to_string()).unwrap_or_default(),
+        });
+    }
+}
+
+/// Records the fields of every struct and the variants of every enum.
+fn collect_fields(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for item in node.descendants() {
+        let range = range_from_text_range(item.text_range());
+        if let Some(strukt) = ast::Struct::cast(item.clone()) {
+            let fields: Vec<_> = match strukt.field_list() {
+                Some(ast::FieldList::RecordFieldList(list)) => list
+                    .fields()
+                    .map(|field| (field.name().map(|name| name.text().to_string()), field.ty()))
+                    .collect(),
+                Some(ast::FieldList::TupleFieldList(list)) => list
+                    .fields()
+                    .enumerate()
+                    .map(|(idx, field)| (Some(idx.to_string()), field.ty()))
+                    .collect(),
+                None => Vec::new(),
+            };
+            for (name, ty) in fields {
+                let ty = ty.map(|ty| ty.syntax().text().to_string()).unwrap_or_default();
+                metadata.push(Metadata {
+                    range: range.clone(),
+                    key: METADATA_FIELD.to_owned(),
+                    value: format!("{}:{ty}", name.unwrap_or_default()),
+                });
+            }
+        } else if let Some(enum_) = ast::Enum::cast(item) {
+            let variants = enum_.variant_list().into_iter().flat_map(|list| list.variants());
+            for name in variants.filter_map(|variant| variant.name()) {
+                metadata.push(Metadata {
+                    range: range.clone(),
+                    key: METADATA_VARIANT.to_owned(),
+                    value: name.text().to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// Records the items declared in every trait.
+fn collect_trait_items(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for trait_ in node.descendants().filter_map(ast::Trait::cast) {
+        let range = range_from_text_range(trait_.syntax().text_range());
+        let items = trait_.assoc_item_list().into_iter().flat_map(|list| list.assoc_items());
+        for item in items {
+            let (kind, default_start) = match &item {
+                ast::AssocItem::Fn(it) => {
+                    (TraitItemKind::Fn, it.body().map(|body| body.syntax().text_range().start()))
+                }
+                ast::AssocItem::TypeAlias(it) => {
+                    (TraitItemKind::Type, it.eq_token().map(|eq| eq.text_range().start()))
+                }
+                ast::AssocItem::Const(it) => {
+                    (TraitItemKind::Const, it.eq_token().map(|eq| eq.text_range().start()))
+                }
+                ast::AssocItem::MacroCall(_) => continue,
+            };
+            let item = TraitItem {
+                kind,
+                signature: declaration_text(item.syntax(), default_start),
+                has_default: default_start.is_some(),
+            };
+            metadata.push(Metadata {
+                range: range.clone(),
+                key: METADATA_TRAIT_ITEM.to_owned(),
+                value: item.to_metadata_value(),
+            });
+        }
+    }
+}
+
+/// Returns the text of `item` from its first token after the attributes and doc comments up to
+/// `end`, or up to its end without the trailing `;`.
+fn declaration_text(item: &SyntaxNode, end: Option<TextSize>) -> String {
+    let item_range = item.text_range();
+    let start = item
+        .children_with_tokens()
+        .find(|it| !it.kind().is_trivia() && it.kind() != SyntaxKind::ATTR)
+        .map_or(item_range.start(), |it| it.text_range().start());
+    let end = end.unwrap_or(item_range.end());
+    let text = item.text().to_string();
+    let relative = |offset: TextSize| usize::from(offset - item_range.start());
+    text[relative(start)..relative(end)].trim_end().trim_end_matches(';').trim_end().to_owned()
+}
+
+/// Records the trait and self type of every impl block.
+fn collect_impls(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for impl_ in node.descendants().filter_map(ast::Impl::cast) {
+        // Incomplete impls without a self type have nothing to index them by.
+        let Some(target) = impl_.self_ty() else { continue };
+        let range = range_from_text_range(impl_.syntax().text_range());
+        metadata.push(Metadata {
+            range: range.clone(),
+            key: METADATA_IMPL_TARGET.to_owned(),
+            value: target.syntax().text().to_string(),
+        });
+        if let Some(trait_) = impl_.trait_() {
+            let negation = if impl_.excl_token().is_some() { "!" } else { "" };
+            metadata.push(Metadata {
+                range,
+                key: METADATA_IMPL_TRAIT.to_owned(),
+                value: format!("{negation}{}", trait_.syntax().text()),
+            });
+        }
+    }
+}
+
+/// Records the flattened paths of every `use` item.
+fn collect_imports(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    fn flatten(tree: ast::UseTree, prefix: &str, imports: &mut Vec<String>) {
+        let mut path = prefix.to_owned();
+        if let Some(segment) = tree.path() {
+            let segment = segment.syntax().text().to_string();
+            // `self` in a group refers to the prefix itself.
+            if segment != "self" || path.is_empty() {
+                if !path.is_empty() {
+                    path.push_str("::");
+                }
+                path.push_str(&segment);
+            }
+        }
+        if let Some(list) = tree.use_tree_list() {
+            for tree in list.use_trees() {
+                flatten(tree, &path, imports);
+            }
+            return;
+        }
+        if tree.star_token().is_some() {
+            path.push_str(if path.is_empty() { "*" } else { "::*" });
+        }
+        if let Some(rename) = tree.rename() {
+            let name = rename.name().map_or_else(|| "_".to_owned(), |name| name.text().to_string());
+            path.push_str(" as ");
+            path.push_str(&name);
+        }
+        imports.push(path);
+    }
+    for use_ in node.descendants().filter_map(ast::Use::cast) {
+        let Some(tree) = use_.use_tree() else { continue };
+        let range = range_from_text_range(use_.syntax().text_range());
+        let mut imports = Vec::new();
+        flatten(tree, "", &mut imports);
+        for import in imports {
+            metadata.push(Metadata {
+                range: range.clone(),
+                key: METADATA_IMPORT.to_owned(),
+                value: import,
+            });
+        }
+    }
+}
+
+/// Records the text of the inner doc comments that precede the first item of the file.
+///
+/// Like rustdoc, a single space after the `//!` marker is dropped, and block comments are trimmed.
+fn collect_module_doc(root: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    let mut range: Option<TextRange> = None;
+    let mut lines = Vec::new();
+    for child in root.children_with_tokens() {
+        match child {
+            NodeOrToken::Node(node) if node.kind() == SyntaxKind::ATTR => continue,
+            NodeOrToken::Node(_) => break,
+            NodeOrToken::Token(token) => {
+                let Some(comment) = ast::Comment::cast(token).filter(|comment| comment.is_inner())
+                else {
+                    continue;
+                };
+                let Some(text) = comment.doc_comment() else { continue };
+                lines.push(match comment.kind().shape {
+                    ast::CommentShape::Line => text.strip_prefix(' ').unwrap_or(text).to_owned(),
+                    ast::CommentShape::Block => text.trim().to_owned(),
+                });
+                let comment_range = comment.syntax().text_range();
+                range = Some(range.map_or(comment_range, |range| range.cover(comment_range)));
+            }
+        }
+    }
+    if let Some(range) = range {
+        metadata.push(Metadata {
+            range: range_from_text_range(range),
+            key: METADATA_MODULE_DOC.to_owned(),
+            value: lines.join("\n"),
+        });
+    }
+}
+
+/// Records the size of the initializer of every `const` and `static` item that has one.
+fn collect_initializer_sizes(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for item in node.descendants() {
+        let body = match_ast! {
+            match item {
+                ast::Const(it) => it.body(),
+                ast::Static(it) => it.body(),
+                _ => continue,
+            }
+        };
+        let Some(body) = body else { continue };
+        metadata.push(Metadata {
+            range: range_from_text_range(item.text_range()),
+            key: METADATA_INITIALIZER_NODES.to_owned(),
+            value: body.syntax().descendants().count().to_string(),
+        });
+    }
+}
+
+/// Records the generic parameter names of every item with a generic parameter list.
+fn collect_generics(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for params in node.descendants().filter_map(ast::GenericParamList::cast) {
+        let Some(item) = params.syntax().parent() else { continue };
+        let range = range_from_text_range(item.text_range());
+        for param in params.generic_params() {
+            let name = match param {
+                ast::GenericParam::LifetimeParam(param) => {
+                    param.lifetime().map(|lifetime| lifetime.text().to_string())
+                }
+                ast::GenericParam::TypeParam(param) => {
+                    param.name().map(|name| name.text().to_string())
+                }
+                ast::GenericParam::ConstParam(param) => {
+                    param.name().map(|name| name.text().to_string())
+                }
+            };
+            let Some(name) = name else { continue };
+            metadata.push(Metadata {
+                range: range.clone(),
+                key: METADATA_GENERIC.to_owned(),
+                value: name,
+            });
+        }
+    }
+}
+
+/// Records the number of lines spanned by every item.
+fn collect_line_spans(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for item in node.descendants().filter(|node| is_outline_item(node.kind())) {
+        let lines = item.text().to_string().matches('\n').count() + 1;
+        metadata.push(Metadata {
+            range: range_from_text_range(item.text_range()),
+            key: METADATA_LINE_SPAN.to_owned(),
+            value: lines.to_string(),
+        });
+    }
+}
+
+/// Records the name of every function annotated with a test attribute, see
+/// `ParseOptions::capture_tests`.
+fn collect_tests(node: &SyntaxNode, metadata: &mut Vec<Metadata>) {
+    for func in node.descendants().filter_map(ast::Fn::cast) {
+        let is_test = func.attrs().any(|attr| {
+            let Some(path) = attr.path() else { return false };
+            path.segment()
+                .and_then(|segment| segment.name_ref())
+                .is_some_and(|name| name.text() == "test")
+        });
+        let Some(name) = func.name().filter(|_| is_test) else { continue };
+        metadata.push(Metadata {
+            range: range_from_text_range(func.syntax().text_range()),
+            key: METADATA_TEST.to_owned(),
+            value: name.text().to_string(),
+        });
+    }
+}
+
+/// Collects the derives in the arguments of a `cfg_attr`, which may itself contain `cfg_attr`s.
+fn derives_in_cfg_attr(tt: &ast::TokenTree, names: &mut Vec<String>) {
+    let mut last_ident = None;
+    for child in tt.syntax().children_with_tokens() {
+        match child {
+            NodeOrToken::Token(token) if token.kind() == SyntaxKind::IDENT => {
+                last_ident = Some(token);
+            }
+            NodeOrToken::Token(token) if token.kind().is_trivia() => {}
+            NodeOrToken::Node(node) => {
+                if let Some(inner) = ast::TokenTree::cast(node) {
+                    match last_ident.as_ref().map(|ident| ident.text()) {
+                        Some("derive") => names.extend(comma_separated(&inner)),
+                        Some("cfg_attr") => derives_in_cfg_attr(&inner, names),
+                        _ => {}
+                    }
+                }
+                last_ident = None;
+            }
+            NodeOrToken::Token(_) => last_ident = None,
+        }
+    }
+}
+
+/// Splits the contents of a token tree at its top-level commas, dropping whitespace.
+fn comma_separated(tt: &ast::TokenTree) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let children = tt.syntax().children_with_tokens();
+    // Skip the delimiters.
+    let len = children.clone().count();
+    for child in children.skip(1).take(len.saturating_sub(2)) {
+        match child {
+            NodeOrToken::Token(token) if token.kind() == SyntaxKind::COMMA => {
+                items.push(std::mem::take(&mut current));
+            }
+            NodeOrToken::Token(token) if token.kind().is_trivia() => {}
+            NodeOrToken::Token(token) => current.push_str(token.text()),
+            NodeOrToken::Node(node) => current.push_str(&node.text().to_string()),
+        }
+    }
+    items.push(current);
+    items.retain(|item| !item.is_empty());
+    items
+}
+
 /// Converts a TextRange to asset::Range.
 fn range_from_text_range(r: TextRange) -> Range {
-    Range {
-        offset: r.start().into(),
-        end_offset: r.end().into(),
+    Range { offset: r.start().into(), end_offset: r.end().into() }
+}
+
+/// Controls what `dump_directory` parses, keeps and writes.
+#[derive(Default)]
+pub struct DumpOptions {
+    /// How to parse each file.
+    pub parse: ParseOptions,
+    /// Leave out files whose `File::error_node_ratio` exceeds this ratio, usually
+    /// `DEFAULT_UNPARSEABLE_THRESHOLD`.
+    pub skip_unparseable: Option<f64>,
+    /// Where to write the asset. If unset, the project is only returned.
+    pub out: Option<PathBuf>,
+    /// Stop walking `root` after this many `.rs` files, whether they are kept or skipped, so
+    /// that pointing the dump at a huge tree by accident ends quickly.
+    pub max_files: Option<usize>,
+    /// Rewrites the content of each file before it is parsed, e.g. to redact secrets.
+    ///
+    /// The transformed text is both parsed and stored, so all offsets in the asset refer to it
+    /// rather than to the file on disk.
+    pub transform_content: Option<Box<dyn Fn(&str) -> String>>,
+}
+
+impl std::fmt::Debug for DumpOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DumpOptions")
+            .field("parse", &self.parse)
+            .field("skip_unparseable", &self.skip_unparseable)
+            .field("out", &self.out)
+            .field("max_files", &self.max_files)
+            .field("transform_content", &self.transform_content.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+/// Reports the progress of `dump_directory`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpEvent {
+    /// A file was parsed and will be part of the asset.
+    FileParsed { path: String, nodes: usize, errors: usize },
+    /// A file was left out, because it could not be read or is not Rust.
+    FileSkipped { path: String, reason: String },
+    /// The walk stopped early because there are more than `DumpOptions::max_files` files.
+    LimitReached { max_files: usize },
+    /// All files are parsed and the asset is being written.
+    EncodeStarted,
+    /// The dump is complete.
+    Done,
+}
+
+/// Parses every `.rs` file under `root`, in file name order, and writes the project to
+/// `options.out` if set, reporting each step to `progress`.
+///
+/// Files that cannot be read are skipped rather than failing the dump; failing to write the
+/// asset is an error.
+pub fn dump_directory(
+    root: &Path,
+    options: &DumpOptions,
+    progress: &mut dyn FnMut(DumpEvent),
+) -> anyhow::Result<Project> {
+    let mut files = Vec::new();
+    let mut matched = 0;
+    for entry in walkdir::WalkDir::new(root).sort_by_file_name().into_iter().filter_map(Result::ok)
+    {
+        let path = entry.path();
+        if !path.is_file() || path.extension().is_none_or(|ext| ext != "rs") {
+            continue;
+        }
+        if let Some(max_files) = options.max_files.filter(|&max_files| matched >= max_files) {
+            progress(DumpEvent::LimitReached { max_files });
+            break;
+        }
+        matched += 1;
+        let path = path.display().to_string();
+        let text = match std::fs::read_to_string(entry.path()) {
+            Ok(text) => text,
+            Err(err) => {
+                progress(DumpEvent::FileSkipped { path, reason: format!("failed to read: {err}") });
+                continue;
+            }
+        };
+        let text = match &options.transform_content {
+            Some(transform) => transform(&text),
+            None => text,
+        };
+        let file = parse_rust_to_asset_file_with_options(path, text, &options.parse);
+        if let Some(threshold) = options.skip_unparseable {
+            let ratio = file.error_node_ratio();
+            if ratio > threshold {
+                let reason = format!("unparseable, {:.0}% error nodes", ratio * 100.0);
+                progress(DumpEvent::FileSkipped { path: file.path, reason });
+                continue;
+            }
+        }
+        progress(DumpEvent::FileParsed {
+            path: file.path.clone(),
+            nodes: file.tree.len(),
+            errors: file.errors.len(),
+        });
+        files.push(file);
+    }
+    let project = Project { files };
+    if let Some(out) = &options.out {
+        progress(DumpEvent::EncodeStarted);
+        let out_file = std::fs::File::create(out)
+            .map_err(|err| anyhow::anyhow!("failed to create {}: {err}", out.display()))?;
+        let mut writer = BufWriter::new(out_file);
+        project.encode(&mut writer)?;
+        std::io::Write::flush(&mut writer)?;
     }
+    progress(DumpEvent::Done);
+    Ok(project)
 }
 
 /// Parses a Rust file and produces an asset::File.
 pub fn parse_rust_to_asset_file(path: String, content: String) -> File {
-    let parse = SourceFile::parse(&content, Edition::CURRENT);
-    let tree = flatten_ast(&parse.syntax_node());
-    let errors = parse.errors().into_iter().map(|err| {
-        Annotation {
-            range: range_from_text_range(err.range()),
-            text: err.to_string(),
-        }
-    }).collect();
+    parse_rust_to_asset_file_with_options(path, content, &ParseOptions::default())
+}
 
-    File {
+/// Parses a Rust file and produces an asset::File, capturing the extra data selected by `options`.
+pub fn parse_rust_to_asset_file_with_options(
+    path: String,
+    content: String,
+    options: &ParseOptions,
+) -> File {
+    let mut content = content;
+    let bom_stripped = options.strip_bom && content.starts_with('\u{feff}');
+    if bom_stripped {
+        content.drain(..'\u{feff}'.len_utf8());
+    }
+    let edition = options.edition.unwrap_or(Edition::CURRENT);
+    let parse = SourceFile::parse(&content, edition);
+    let mut file = file_from_parse(path, content, &parse, edition, options);
+    if bom_stripped {
+        file.metadata.insert(
+            0,
+            Metadata {
+                range: Range { offset: 0, end_offset: 0 },
+                key: METADATA_BOM_STRIPPED.to_owned(),
+                value: String::new(),
+            },
+        );
+    }
+    file
+}
+
+/// Converts a parsed `content` into an asset::File, capturing the extra data selected by `options`.
+fn file_from_parse(
+    path: String,
+    content: String,
+    parse: &syntax::Parse<SourceFile>,
+    edition: Edition,
+    options: &ParseOptions,
+) -> File {
+    let root = parse.syntax_node();
+    let tree = if options.outline {
+        flatten_outline(&root, options.subtree_hashes)
+    } else {
+        flatten_ast(&root, options.subtree_hashes)
+    };
+    let mut errors = parse_errors(parse, &content, options.error_context);
+    let mut literals = Vec::new();
+    let mut metadata = Vec::new();
+    if !options.outline {
+        collect_captures(&root, options, &mut errors, &mut literals, &mut metadata);
+    }
+    let shebang = root
+        .first_token()
+        .filter(|token| token.kind() == SyntaxKind::SHEBANG)
+        .map(|token| token.text().trim_end().to_owned());
+    let mut file = File {
         path,
         content,
         tree,
         errors,
+        literals,
+        metadata,
+        shebang,
+        edition,
+        line_offsets: Vec::new(),
+    };
+    if options.capture_line_offsets {
+        file.line_offsets = file.compute_line_offsets();
+    }
+    if options.anonymize {
+        anonymize(&root, &mut file);
+    }
+    file
+}
+
+/// Runs the `capture_*` and `flag_*` passes selected by `options` over `root`.
+fn collect_captures(
+    root: &SyntaxNode,
+    options: &ParseOptions,
+    errors: &mut Vec<Annotation>,
+    literals: &mut Vec<Literal>,
+    metadata: &mut Vec<Metadata>,
+) {
+    if options.flag_unresolved_macros {
+        collect_unresolved_macros(root, errors);
+    }
+    if options.flag_unsafe {
+        collect_unsafe(root, errors);
+    }
+    if options.capture_literals {
+        *literals = collect_literals(root);
+    }
+    if options.capture_derives {
+        collect_derives(root, metadata);
+    }
+    if options.capture_generics {
+        collect_generics(root, metadata);
+    }
+    if options.capture_visibility {
+        collect_visibility(root, metadata);
+    }
+    if options.capture_signatures {
+        collect_signatures(root, metadata);
+    }
+    if options.capture_impls {
+        collect_impls(root, metadata);
+    }
+    if options.capture_fields {
+        collect_fields(root, metadata);
+    }
+    if options.capture_trait_items {
+        collect_trait_items(root, metadata);
+    }
+    if options.capture_imports {
+        collect_imports(root, metadata);
+    }
+    if options.capture_module_doc {
+        collect_module_doc(root, metadata);
+    }
+    if options.capture_initializer_sizes {
+        collect_initializer_sizes(root, metadata);
+    }
+    if options.capture_macro_calls {
+        collect_macro_calls(root, metadata);
+    }
+    if options.capture_line_spans {
+        collect_line_spans(root, metadata);
+    }
+    if options.capture_tests {
+        collect_tests(root, metadata);
+    }
+    if options.capture_trivia {
+        collect_trivia(root, metadata);
+    }
+}
+
+/// Replaces all identifier and literal text of `file` by pseudonyms, see `ParseOptions::anonymize`.
+fn anonymize(root: &SyntaxNode, file: &mut File) {
+    fn pseudonym(names: &mut FxHashMap<String, String>, prefix: &str, text: &str) -> String {
+        let next = names.len() + 1;
+        names.entry(text.to_owned()).or_insert_with(|| format!("{prefix}_{next}")).clone()
+    }
+    fn pseudonym_path(idents: &mut FxHashMap<String, String>, path: &str) -> String {
+        let segments: Vec<_> = path
+            .split("::")
+            .map(str::trim)
+            .map(|it| match it {
+                "crate" | "self" | "super" => it.to_owned(),
+                _ => pseudonym(idents, "var", it),
+            })
+            .collect();
+        segments.join("::")
+    }
+    let mut idents = FxHashMap::default();
+    let mut lits = FxHashMap::default();
+    file.metadata.retain(|entry| {
+        ![
+            METADATA_PARAM,
+            METADATA_RETURN,
+            METADATA_IMPL_TARGET,
+            METADATA_IMPL_TRAIT,
+            METADATA_IMPORT,
+            METADATA_MODULE_DOC,
+            METADATA_FIELD,
+            METADATA_VARIANT,
+            METADATA_TRAIT_ITEM,
+        ]
+        .contains(&entry.key.as_str())
+    });
+    for entry in &mut file.metadata {
+        if [METADATA_DERIVE, METADATA_GENERIC, METADATA_MACRO_CALL, METADATA_TEST]
+            .contains(&entry.key.as_str())
+        {
+            entry.value = pseudonym_path(&mut idents, &entry.value);
+        } else if entry.key == METADATA_VISIBILITY {
+            if let Some(Visibility::Restricted(restricted)) =
+                Visibility::from_metadata_value(&entry.value)
+            {
+                entry.value = Visibility::Restricted(pseudonym_path(&mut idents, &restricted))
+                    .to_metadata_value();
+            }
+        }
+    }
+    for literal in &mut file.literals {
+        let text = &file.content[literal.range.offset..literal.range.end_offset];
+        literal.value = pseudonym(&mut lits, "lit", text);
+    }
+    for token in root.descendants_with_tokens().filter_map(|it| it.into_token()) {
+        let value = match token.kind() {
+            SyntaxKind::IDENT => pseudonym(&mut idents, "var", token.text()),
+            kind if kind.is_literal() => pseudonym(&mut lits, "lit", token.text()),
+            _ => continue,
+        };
+        file.metadata.push(Metadata {
+            range: range_from_text_range(token.text_range()),
+            key: METADATA_TOKEN.to_owned(),
+            value,
+        });
+    }
+    file.content.clear();
+    file.shebang = None;
+    for ann in &mut file.errors {
+        ann.context = None;
+        let macro_path =
+            ann.text.strip_prefix(UNRESOLVED_MACRO_MESSAGE).and_then(|it| it.strip_suffix('!'));
+        if let Some(macro_path) = macro_path.filter(|_| ann.severity == Severity::Warning) {
+            let macro_path = pseudonym_path(&mut idents, macro_path);
+            ann.text = format!("{UNRESOLVED_MACRO_MESSAGE}{macro_path}!");
+        }
+    }
+}
+
+/// Converts the syntax errors of `parse` into `Severity::Error` annotations.
+fn parse_errors(
+    parse: &syntax::Parse<SourceFile>,
+    content: &str,
+    max_context: Option<usize>,
+) -> Vec<Annotation> {
+    let root = parse.syntax_node();
+    parse
+        .errors()
+        .into_iter()
+        .map(|err| Annotation {
+            range: range_from_text_range(err.range()),
+            text: err.to_string(),
+            severity: Severity::Error,
+            context: max_context.map(|max_len| error_context(&root, content, err.range(), max_len)),
+        })
+        .collect()
+}
+
+/// Returns the text of `content` in `range`, or of the next non-trivia token if `range` is empty,
+/// truncated to at most `max_len` bytes, see `ParseOptions::error_context`.
+fn error_context(root: &SyntaxNode, content: &str, range: TextRange, max_len: usize) -> String {
+    let range = if range.is_empty() {
+        std::iter::successors(root.token_at_offset(range.start()).right_biased(), |token| {
+            token.next_token()
+        })
+        .find(|token| !token.kind().is_trivia())
+        .map_or(range, |token| token.text_range())
+    } else {
+        range
+    };
+    let text = &content[range];
+    let mut end = text.len().min(max_len);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_owned()
+}
+
+impl File {
+    /// Parses `new_content` as an edit of `old_content`, returning the new file together with the
+    /// ranges of `new_content` that differ from `old_content`.
+    ///
+    /// The ranges come from a character diff of the two contents, an empty range marks a
+    /// deletion. The span covering all of them is handed to rust-analyzer's incremental reparse,
+    /// which only falls back to a full parse when the edit crosses a reparseable node. The
+    /// returned file has an empty path and captures nothing beyond the tree and errors.
+    pub fn incremental_from(
+        old_content: &str,
+        new_content: &str,
+        edition: Edition,
+    ) -> (File, Vec<Range>) {
+        let mut changed: Vec<Range> = Vec::new();
+        // The changed span in `old_content` coordinates, used for the reparse.
+        let mut deleted: Option<Range> = None;
+        let (mut old_pos, mut new_pos) = (0, 0);
+        for chunk in dissimilar::diff(old_content, new_content) {
+            let (old_len, new_len) = match chunk {
+                dissimilar::Chunk::Equal(text) => {
+                    old_pos += text.len();
+                    new_pos += text.len();
+                    continue;
+                }
+                dissimilar::Chunk::Delete(text) => (text.len(), 0),
+                dissimilar::Chunk::Insert(text) => (0, text.len()),
+            };
+            match changed.last_mut() {
+                Some(last) if last.end_offset == new_pos => last.end_offset += new_len,
+                _ => changed.push(Range { offset: new_pos, end_offset: new_pos + new_len }),
+            }
+            let deleted = deleted.get_or_insert(Range { offset: old_pos, end_offset: old_pos });
+            deleted.end_offset = old_pos + old_len;
+            old_pos += old_len;
+            new_pos += new_len;
+        }
+        let old = SourceFile::parse(old_content, edition);
+        let parse = match &deleted {
+            Some(deleted) => {
+                let delete = TextRange::new(
+                    TextSize::new(deleted.offset as u32),
+                    TextSize::new(deleted.end_offset as u32),
+                );
+                // The insertion covers everything between the first and the last change.
+                let insert_end = new_content.len() - (old_content.len() - deleted.end_offset);
+                old.reparse(delete, &new_content[deleted.offset..insert_end], edition)
+            }
+            None => old,
+        };
+        let file = file_from_parse(
+            String::new(),
+            new_content.to_owned(),
+            &parse,
+            edition,
+            &ParseOptions::default(),
+        );
+        (file, changed)
+    }
+
+    /// Parses the content again and replaces the `Severity::Error` annotations with the syntax
+    /// errors found, leaving the tree and everything else untouched.
+    ///
+    /// Warnings and notes, such as merged lints, are kept after the new errors. This is cheaper
+    /// than a full reparse when only the diagnostics are needed, but the result is only
+    /// consistent if the content still matches the tree. The new errors have no context.
+    pub fn refresh_errors(&mut self, edition: Edition) {
+        let parse = SourceFile::parse(&self.content, edition);
+        let mut errors = parse_errors(&parse, &self.content, None);
+        errors.extend(self.errors.drain(..).filter(|ann| ann.severity != Severity::Error));
+        self.errors = errors;
+    }
+
+    /// Returns the edits that turn the content of `self` into the content of `new`, in order.
+    ///
+    /// Each edit replaces a range of the old content by a string; an empty range is an insertion
+    /// and an empty string a deletion. The edits come from a character diff, with adjacent
+    /// deletions and insertions merged into one replacement, so they never overlap.
+    pub fn diff_edits(&self, new: &File) -> Vec<(Range, String)> {
+        let mut edits: Vec<(Range, String)> = Vec::new();
+        let mut old_pos = 0;
+        for chunk in dissimilar::diff(&self.content, &new.content) {
+            let (deleted, inserted) = match chunk {
+                dissimilar::Chunk::Equal(text) => {
+                    old_pos += text.len();
+                    continue;
+                }
+                dissimilar::Chunk::Delete(text) => (text.len(), ""),
+                dissimilar::Chunk::Insert(text) => (0, text),
+            };
+            match edits.last_mut() {
+                Some((range, text)) if range.end_offset == old_pos => {
+                    range.end_offset += deleted;
+                    text.push_str(inserted);
+                }
+                _ => edits.push((
+                    Range { offset: old_pos, end_offset: old_pos + deleted },
+                    inserted.to_owned(),
+                )),
+            }
+            old_pos += deleted;
+        }
+        edits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::{FnSignature, ImplInfo};
+
+    fn parse(content: &str, options: &ParseOptions) -> File {
+        parse_rust_to_asset_file_with_options("lib.rs".to_owned(), content.to_owned(), options)
+    }
+
+    #[test]
+    fn dump_directory_reports_progress() {
+        let root = std::env::temp_dir().join(format!("asset-dump-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/a.rs"), "fn a() {}").unwrap();
+        std::fs::write(root.join("src/b.rs"), "#!/bin/sh\nif [ -f x ]; then\n  rm -rf x\nfi\n")
+            .unwrap();
+        std::fs::write(root.join("src/c.txt"), "not rust").unwrap();
+        std::fs::write(root.join("src/d.rs"), "fn d( {}").unwrap();
+        let out = root.join("out.asset");
+        let options = DumpOptions {
+            skip_unparseable: Some(DEFAULT_UNPARSEABLE_THRESHOLD),
+            out: Some(out.clone()),
+            ..Default::default()
+        };
+        let mut events = Vec::new();
+        let project = dump_directory(&root, &options, &mut |event| events.push(event)).unwrap();
+        let written = Project::decode(std::fs::File::open(&out).unwrap());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let path = |name: &str| root.join("src").join(name).display().to_string();
+        assert!(
+            matches!(&events[1], DumpEvent::FileSkipped { path: skipped, .. } if *skipped == path("b.rs"))
+        );
+        events[1] = DumpEvent::FileSkipped { path: path("b.rs"), reason: String::new() };
+        assert_eq!(
+            events,
+            [
+                DumpEvent::FileParsed {
+                    path: path("a.rs"),
+                    nodes: project.files[0].tree.len(),
+                    errors: 0
+                },
+                DumpEvent::FileSkipped { path: path("b.rs"), reason: String::new() },
+                DumpEvent::FileParsed {
+                    path: path("d.rs"),
+                    nodes: project.files[1].tree.len(),
+                    errors: project.files[1].errors.len(),
+                },
+                DumpEvent::EncodeStarted,
+                DumpEvent::Done,
+            ]
+        );
+        assert!(!project.files[1].errors.is_empty());
+        assert_eq!(written.unwrap(), project);
+    }
+
+    #[test]
+    fn dump_directory_transforms_content() {
+        let root =
+            std::env::temp_dir().join(format!("asset-dump-transform-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("lib.rs"), "const KEY: &str = \"hunter2\";").unwrap();
+        let options = DumpOptions {
+            transform_content: Some(Box::new(|text: &str| text.replace("\"hunter2\"", "0"))),
+            ..Default::default()
+        };
+        let project = dump_directory(&root, &options, &mut |_| {}).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let file = &project.files[0];
+        assert_eq!(file.content, "const KEY: &str = 0;");
+        let literal = file.tree.iter().find(|node| node.node_type == "LITERAL").unwrap();
+        assert_eq!(&file.content[literal.range.offset..literal.range.end_offset], "0");
+        assert_eq!(literal.range.end_offset, file.content.len() - 1);
+    }
+
+    #[test]
+    fn dump_directory_stops_at_max_files() {
+        let root = std::env::temp_dir().join(format!("asset-dump-max-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for name in ["a.rs", "b.rs", "c.rs", "d.rs"] {
+            std::fs::write(root.join(name), "fn f() {}").unwrap();
+        }
+        let options = DumpOptions { max_files: Some(2), ..Default::default() };
+        let mut events = Vec::new();
+        let project = dump_directory(&root, &options, &mut |event| events.push(event)).unwrap();
+        let unlimited = dump_directory(&root, &DumpOptions::default(), &mut |_| {}).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<_> =
+            project.files.iter().map(|file| Path::new(&file.path).file_name().unwrap()).collect();
+        assert_eq!(names, ["a.rs", "b.rs"]);
+        assert_eq!(events[2..], [DumpEvent::LimitReached { max_files: 2 }, DumpEvent::Done]);
+        assert_eq!(unlimited.files.len(), 4);
+    }
+
+    #[test]
+    fn error_node_ratio_separates_rust_from_other_languages() {
+        let rust = parse("fn main() {\n    let x = ;\n}\n", &ParseOptions::default());
+        let shell = parse(
+            "#!/bin/sh\nset -e\nif [ -f x ]; then\n  rm -rf /tmp/x\nfi\nfor i in 1 2 3; do echo $i; done\n",
+            &ParseOptions::default(),
+        );
+        assert_eq!(parse("fn main() {}\n", &ParseOptions::default()).error_node_ratio(), 0.0);
+        let rust_ratio = rust.error_node_ratio();
+        assert!(rust_ratio > 0.0 && rust_ratio < DEFAULT_UNPARSEABLE_THRESHOLD, "{rust_ratio}");
+        let shell_ratio = shell.error_node_ratio();
+        assert!(shell_ratio > DEFAULT_UNPARSEABLE_THRESHOLD, "{shell_ratio}");
+    }
+
+    #[test]
+    fn incremental_from_reports_tight_changes() {
+        let old = "fn f() {\n    let x = 1;\n}\nfn g() {}\n";
+        let new = "fn f() {\n    let x = 2;\n}\nfn g() {}\n";
+        let (file, changed) = File::incremental_from(old, new, Edition::CURRENT);
+        let pos = new.find('2').unwrap();
+        assert_eq!(changed, vec![Range { offset: pos, end_offset: pos + 1 }]);
+        let full = parse(new, &ParseOptions::default());
+        assert_eq!(file.tree, full.tree);
+        assert_eq!(file.content, new);
+
+        let (file, changed) = File::incremental_from(new, old, Edition::CURRENT);
+        assert_eq!(changed, vec![Range { offset: pos, end_offset: pos + 1 }]);
+        assert_eq!(file.tree, parse(old, &ParseOptions::default()).tree);
+
+        let (_, changed) = File::incremental_from(
+            old,
+            "fn f() {\n    let  = 1;\n}\nfn g() {}\n",
+            Edition::CURRENT,
+        );
+        let pos = old.find('x').unwrap();
+        assert_eq!(changed, vec![Range { offset: pos, end_offset: pos }]);
+        assert!(File::incremental_from(old, old, Edition::CURRENT).1.is_empty());
+    }
+
+    #[test]
+    fn refresh_errors_keeps_tree() {
+        let options = ParseOptions { flag_unsafe: true, ..Default::default() };
+        let mut file = parse("unsafe fn f() { let _ = '\\u{zz}'; }", &options);
+        let tree = file.tree.clone();
+        assert_eq!(file.errors.iter().filter(|ann| ann.severity == Severity::Error).count(), 2);
+        file.content = "unsafe fn f() { let _ = '\\u{41}'; }".to_owned();
+        file.refresh_errors(Edition::CURRENT);
+        assert_eq!(file.tree, tree);
+        let texts: Vec<_> = file.errors.iter().map(|ann| ann.text.as_str()).collect();
+        assert_eq!(texts, ["unsafe"]);
+        assert_eq!(file.tree, parse(&file.content, &options).tree);
+
+        file.content = "unsafe fn f() { let _ = '\\u{zz}' }".to_owned();
+        file.refresh_errors(Edition::CURRENT);
+        let texts: Vec<_> = file.errors.iter().map(|ann| ann.text.as_str()).collect();
+        assert!(texts.contains(&"expected SEMICOLON"));
+        assert_eq!(texts.last(), Some(&"unsafe"));
+    }
+
+    #[test]
+    fn diff_edits_transform_old_into_new() {
+        let old = parse("fn f() {}\nfn g() {}\n", &ParseOptions::default());
+        let new = parse("fn f() {}\npub fn g() {}\n", &ParseOptions::default());
+        let pos = old.content.find("fn g").unwrap();
+        assert_eq!(
+            old.diff_edits(&new),
+            [(Range { offset: pos, end_offset: pos }, "pub ".to_owned())]
+        );
+
+        let new = parse("fn f(x: u32) {}\n", &ParseOptions::default());
+        let mut applied = old.content.clone();
+        for (range, text) in old.diff_edits(&new).iter().rev() {
+            applied.replace_range(range.offset..range.end_offset, text);
+        }
+        assert_eq!(applied, new.content);
+        assert!(old.diff_edits(&old).is_empty());
+    }
+
+    #[test]
+    fn anonymize_pseudonymizes_consistently() {
+        let content = r#"#[derive(Secret)]
+struct Secret { key: u32 }
+fn leak(key: Secret) -> u32 { println!("{}", "hunter2"); key.key + 42 + 42 }
+fn hidden() { internal::secret_macro!; }
+"#;
+        let options = ParseOptions {
+            anonymize: true,
+            flag_unresolved_macros: true,
+            capture_literals: true,
+            capture_derives: true,
+            capture_macro_calls: true,
+            ..Default::default()
+        };
+        let file = parse(content, &options);
+        assert!(file.content.is_empty());
+        assert_eq!(file.tree, parse(content, &ParseOptions::default()).tree);
+
+        let tokens: Vec<(&str, &str)> = file
+            .metadata
+            .iter()
+            .filter(|entry| entry.key == METADATA_TOKEN)
+            .map(|entry| {
+                (&content[entry.range.offset..entry.range.end_offset], entry.value.as_str())
+            })
+            .collect();
+        let mut seen = FxHashMap::default();
+        for &(text, pseudonym) in &tokens {
+            assert_eq!(*seen.entry(text).or_insert(pseudonym), pseudonym, "{text}");
+            assert!(!pseudonym.contains(text), "{text} leaked");
+        }
+        assert_eq!(seen.len(), seen.values().collect::<rustc_hash::FxHashSet<_>>().len());
+        assert_eq!(seen["Secret"], "var_1");
+        assert!(seen["42"].starts_with("lit_"));
+        assert_eq!(file.derives(&file.tree[1]), vec![seen["Secret"]]);
+        assert_eq!(file.macro_calls()[0].0, seen["println"]);
+        assert!(file.literals.iter().all(|lit| lit.value.starts_with("lit_")));
+        let warnings: Vec<_> = file
+            .errors
+            .iter()
+            .filter(|ann| ann.severity == Severity::Warning)
+            .map(|ann| ann.text.as_str())
+            .collect();
+        let expected =
+            format!("unresolved macro invocation: {}::{}!", seen["internal"], seen["secret_macro"]);
+        assert_eq!(warnings, [expected.as_str()]);
+    }
+
+    #[test]
+    fn trivia_is_associated() {
+        let content = "fn a() {} // x\n// y\nfn b() {}\n\n// z\n\nfn c() {}\n";
+        let file = parse(content, &ParseOptions { capture_trivia: true, ..Default::default() });
+        let association = |comment: &str| {
+            let offset = content.find(comment).unwrap();
+            file.trivia().into_iter().find(|(range, _)| range.offset == offset).unwrap().1
+        };
+        assert_eq!(association("// x"), TriviaAssociation::Trailing);
+        assert_eq!(association("// y"), TriviaAssociation::Leading);
+        assert_eq!(association("// z"), TriviaAssociation::Standalone);
+        // Whitespace follows the same rule.
+        assert_eq!(association(" // x"), TriviaAssociation::Trailing);
+        assert_eq!(association("\nfn b"), TriviaAssociation::Leading);
+        assert_eq!(association("\n\nfn c"), TriviaAssociation::Standalone);
+    }
+
+    #[test]
+    fn equal_range_nodes_are_ordered_by_depth_then_kind() {
+        let file = parse("fn f() { x }", &ParseOptions::default());
+        let x = "fn f() { ".len();
+        let kinds: Vec<&str> = file
+            .tree
+            .iter()
+            .filter(|node| node.range == Range { offset: x, end_offset: x + 1 })
+            .map(|node| node.node_type.as_str())
+            .collect();
+        assert_eq!(kinds, ["PATH_EXPR", "PATH", "PATH_SEGMENT", "NAME_REF"]);
+
+        let node = |offset, end_offset, kind: &str| Node {
+            range: Range { offset, end_offset },
+            node_type: kind.to_owned(),
+            subtree_hash: None,
+        };
+        let ordered = canonical_order(vec![
+            (node(0, 4, "SOURCE_FILE"), 0),
+            (node(2, 2, "ERROR"), 1),
+            (node(2, 2, "ERROR_CHILD"), 2),
+            (node(2, 2, "DEEP"), 3),
+            (node(2, 2, "B_EMPTY"), 1),
+            (node(2, 2, "A_EMPTY"), 1),
+            (node(2, 4, "ITEM"), 1),
+        ]);
+        let kinds: Vec<&str> = ordered.iter().map(|node| node.node_type.as_str()).collect();
+        assert_eq!(
+            kinds,
+            ["SOURCE_FILE", "A_EMPTY", "B_EMPTY", "ERROR", "ERROR_CHILD", "DEEP", "ITEM"]
+        );
+    }
+
+    #[test]
+    fn edition_is_recorded() {
+        // `async` is an identifier in 2015 and a keyword since 2018.
+        let content = "fn f() { let async = 1; }";
+        let old = ParseOptions { edition: Some(Edition::Edition2015), ..Default::default() };
+        let old = parse(content, &old);
+        let new = parse(content, &ParseOptions::default());
+        assert_eq!(old.edition, Edition::Edition2015);
+        assert!(old.errors.is_empty());
+        assert_eq!(new.edition, Edition::CURRENT);
+        assert!(!new.errors.is_empty());
+
+        let project = crate::asset::Project { files: vec![old, new] };
+        let mut buf = std::io::Cursor::new(Vec::new());
+        project.encode(&mut buf).unwrap();
+        buf.set_position(0);
+        assert_eq!(crate::asset::Project::decode(buf).unwrap(), project);
+    }
+
+    #[test]
+    fn tests_are_captured() {
+        let content = r#"
+#[test]
+fn works() {}
+
+fn helper() {}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn nested() {}
+    #[cfg(test)]
+    fn not_a_test() {}
+}
+"#;
+        let file = parse(content, &ParseOptions { capture_tests: true, ..Default::default() });
+        let tests = file.tests();
+        let names: Vec<&str> = tests.iter().map(|test| test.name.as_str()).collect();
+        assert_eq!(names, ["works", "nested"]);
+        let start = content.find("#[test]").unwrap();
+        let end = content.find("fn works() {}").unwrap() + "fn works() {}".len();
+        assert_eq!(tests[0].range, Range { offset: start, end_offset: end });
+    }
+
+    #[test]
+    fn bom_is_stripped() {
+        let content = "\u{feff}fn main() {}";
+        let file = parse(content, &ParseOptions { strip_bom: true, ..Default::default() });
+        assert_eq!(file.content, "fn main() {}");
+        assert_eq!(file.tree[0].range, Range { offset: 0, end_offset: 12 });
+        assert_eq!(file.tree[1].range.offset, 0);
+        assert_eq!(file.metadata[0].key, METADATA_BOM_STRIPPED);
+
+        let kept = parse(content, &ParseOptions::default());
+        assert_eq!(kept.content, content);
+        assert!(kept.metadata.is_empty());
+        let plain = parse("fn main() {}", &ParseOptions { strip_bom: true, ..Default::default() });
+        assert!(plain.metadata.is_empty());
+    }
+
+    #[test]
+    fn generics_are_captured() {
+        let content = "fn f<'a, T: Clone, const N: usize>() {}\nstruct S<U>(U);\nfn g() {}";
+        let file = parse(content, &ParseOptions { capture_generics: true, ..Default::default() });
+        let item = |kind: &str, nth: usize| {
+            file.tree.iter().filter(|node| node.node_type == kind).nth(nth).unwrap()
+        };
+        assert_eq!(file.generics(item("FN", 0)), ["'a", "T", "N"]);
+        assert_eq!(file.generics(item("STRUCT", 0)), ["U"]);
+        assert!(file.generics(item("FN", 1)).is_empty());
+    }
+
+    #[test]
+    fn visibility_is_captured() {
+        let content = r#"
+pub fn a() {}
+pub(crate) fn b() {}
+fn c() {}
+mod m {
+    pub(super) struct D;
+    pub(in crate::m) const E: () = ();
+    pub(self) static F: () = ();
+}
+impl X { pub fn g() {} }
+pub use m::D as H;
+use std::mem;
+pub(crate) extern crate alloc as i;
+extern crate core;
+"#;
+        let file = parse(content, &ParseOptions { capture_visibility: true, ..Default::default() });
+        const KINDS: &[&str] =
+            &["CONST", "EXTERN_CRATE", "FN", "MODULE", "STATIC", "STRUCT", "USE"];
+        let visibility = |name: &str| {
+            let offset = content.find(name).unwrap();
+            let item = file
+                .tree
+                .iter()
+                .rev()
+                .find(|node| {
+                    node.range.offset <= offset && KINDS.contains(&node.node_type.as_str())
+                })
+                .unwrap();
+            file.visibility(item)
+        };
+        assert_eq!(visibility("a()"), Some(Visibility::Public));
+        assert_eq!(visibility("b()"), Some(Visibility::Crate));
+        assert_eq!(visibility("c()"), Some(Visibility::Private));
+        assert_eq!(visibility("D;"), Some(Visibility::Super));
+        assert_eq!(visibility("E:"), Some(Visibility::Restricted("crate::m".to_owned())));
+        assert_eq!(visibility("F:"), Some(Visibility::Private));
+        assert_eq!(visibility("g()"), Some(Visibility::Public));
+        assert_eq!(visibility("m {"), Some(Visibility::Private));
+        assert_eq!(visibility("m::D as H"), Some(Visibility::Public));
+        assert_eq!(visibility("std::mem"), Some(Visibility::Private));
+        assert_eq!(visibility("alloc as i"), Some(Visibility::Crate));
+        assert_eq!(visibility("core;"), Some(Visibility::Private));
+        let impl_node = file.tree.iter().find(|node| node.node_type == "IMPL").unwrap();
+        assert_eq!(file.visibility(impl_node), None);
+    }
+
+    #[test]
+    fn literals_are_unescaped() {
+        let options = ParseOptions { capture_literals: true, ..Default::default() };
+        let file =
+            parse(r##"fn f() { let s = "a\nb"; let r = r#"c\n"#; let c = '\t'; }"##, &options);
+        let values: Vec<_> = file.literals.iter().map(|lit| lit.value.as_str()).collect();
+        assert_eq!(values, ["a\nb", "c\\n", "\t"]);
+        let first = &file.literals[0];
+        assert_eq!(first.kind, "STRING");
+        assert_eq!(&file.content[first.range.offset..first.range.end_offset], r#""a\nb""#);
+
+        assert!(parse(r#"fn f() { "x"; }"#, &ParseOptions::default()).literals.is_empty());
+    }
+
+    #[test]
+    fn identical_bodies_have_equal_subtree_hashes() {
+        let options = ParseOptions { subtree_hashes: true, ..Default::default() };
+        let file = parse(
+            "fn a() { let x = 1; x }\nfn b() { let y = 2; y }\nfn c() { let z = 3; }",
+            &options,
+        );
+        let blocks: Vec<_> = file
+            .tree
+            .iter()
+            .filter(|node| node.node_type == "BLOCK_EXPR")
+            .map(|node| node.subtree_hash.unwrap())
+            .collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0], blocks[1]);
+        assert_ne!(blocks[0], blocks[2]);
+        assert!(file.tree.iter().all(|node| node.subtree_hash.is_some()));
+
+        let file = parse("fn a() {}", &ParseOptions::default());
+        assert!(file.tree.iter().all(|node| node.subtree_hash.is_none()));
+    }
+
+    #[test]
+    fn outline_keeps_items_and_names() {
+        let options = ParseOptions { outline: true, ..Default::default() };
+        let file = parse(
+            r#"
+mod a {
+    fn f() { fn local() {} }
+    mod b {
+        fn g() {}
+    }
+}
+impl S {
+    const C: u32 = 0;
+}
+struct S;
+"#,
+            &options,
+        );
+        let outline: Vec<_> = file
+            .tree
+            .iter()
+            .map(|node| match &*node.node_type {
+                "NAME" => format!("  {}", &file.content[node.range.offset..node.range.end_offset]),
+                kind => kind.to_owned(),
+            })
+            .collect();
+        assert_eq!(
+            outline,
+            [
+                "MODULE", "  a", "FN", "  f", "MODULE", "  b", "IMPL", "CONST", "  C", "STRUCT",
+                "  S"
+            ]
+        );
+
+        // The capture passes are skipped, the parse errors are kept.
+        let options = ParseOptions {
+            outline: true,
+            capture_literals: true,
+            capture_visibility: true,
+            flag_unsafe: true,
+            ..Default::default()
+        };
+        let file = parse("pub fn f() { unsafe { \"s\" } }\nfn g(", &options);
+        assert_eq!(file.tree.len(), 4);
+        assert!(file.literals.is_empty() && file.metadata.is_empty());
+        assert!(!file.errors.is_empty());
+        assert!(file.errors.iter().all(|ann| ann.severity == Severity::Error));
+    }
+
+    #[test]
+    fn macro_calls_are_captured() {
+        let options = ParseOptions { capture_macro_calls: true, ..Default::default() };
+        let file = parse(
+            r#"
+macro_rules! m { () => {} }
+m!{}
+fn f() { println!("x"); std::dbg![1]; }
+"#,
+            &options,
+        );
+        let calls: Vec<_> = file
+            .macro_calls()
+            .into_iter()
+            .map(|(name, range)| (name, &file.content[range.offset..range.end_offset]))
+            .collect();
+        assert_eq!(calls, [("m", "{}"), ("println", r#"("x")"#), ("std::dbg", "[1]")]);
+    }
+
+    #[test]
+    fn unresolved_macros_are_flagged() {
+        let options = ParseOptions { flag_unresolved_macros: true, ..Default::default() };
+        let content = "fn f() {\n    undefined!;\n    let x = std::bar! 1;\n    ok!(a b);\n    \
+                       let v = vec![1] let y = 2;\n}\n";
+        let file = parse(content, &options);
+        let flagged: Vec<_> = file
+            .errors
+            .iter()
+            .filter(|ann| ann.severity == Severity::Warning)
+            .map(|ann| (ann.text.as_str(), &content[ann.range.offset..ann.range.end_offset]))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                ("unresolved macro invocation: undefined!", "undefined!"),
+                ("unresolved macro invocation: std::bar!", "std::bar!"),
+            ]
+        );
+        // The error after the complete `vec![1]` is a missing `;`, not about the macro.
+        let missing_semi = content.find("vec![1]").unwrap() + "vec![1]".len();
+        assert!(file.errors.iter().any(|ann| ann.range.offset == missing_semi));
+    }
+
+    #[test]
+    fn unsafe_is_flagged() {
+        let options = ParseOptions { flag_unsafe: true, ..Default::default() };
+        let file = parse(
+            r#"
+unsafe fn f() {}
+fn g() { unsafe { f() } }
+unsafe trait T {}
+unsafe impl T for () {}
+impl Clone for () {}
+"#,
+            &options,
+        );
+        let flagged: Vec<_> = file
+            .errors
+            .iter()
+            .map(|ann| {
+                assert_eq!((ann.severity, ann.text.as_str()), (Severity::Note, "unsafe"));
+                let text = &file.content[ann.range.offset..ann.range.end_offset];
+                text.split(' ').take(2).collect::<Vec<_>>().join(" ")
+            })
+            .collect();
+        assert_eq!(flagged, ["unsafe fn", "unsafe {", "unsafe trait", "unsafe impl"]);
+    }
+
+    #[test]
+    fn error_context_is_captured() {
+        let options =
+            ParseOptions { error_context: Some(5), flag_unsafe: true, ..Default::default() };
+        let file = parse("pub(foo) unsafe fn f() {}\nfn g( {}", &options);
+        let contexts: Vec<_> =
+            file.errors.iter().map(|ann| (ann.text.as_str(), ann.context.as_deref())).collect();
+        assert_eq!(
+            contexts,
+            [
+                ("expected value parameter", Some("{")),
+                ("expected R_PAREN", Some("{")),
+                ("incorrect visibility restriction", Some("pub(f")),
+                ("unsafe", None),
+            ]
+        );
+        let project = Project { files: vec![file.clone()] };
+        let buf = project.encode_to_vec(&Default::default()).unwrap();
+        assert_eq!(project.encoded_size(&Default::default()), buf.len());
+        let decoded = Project::decode(std::io::Cursor::new(buf)).unwrap();
+        assert_eq!(decoded.files[0].errors, file.errors);
+    }
+
+    #[test]
+    fn signatures_are_captured() {
+        let content = "impl S {\n    fn f(&self, x: i32) -> String {}\n    fn g() {}\n}\nstruct S;";
+        let file = parse(content, &ParseOptions { capture_signatures: true, ..Default::default() });
+        let signatures: Vec<_> = file
+            .tree
+            .iter()
+            .filter(|node| node.node_type == "FN")
+            .map(|node| file.signature(node).unwrap())
+            .collect();
+        assert_eq!(
+            signatures,
+            [
+                FnSignature {
+                    params: vec!["&self".to_owned(), "x: i32".to_owned()],
+                    ret: Some("String".to_owned())
+                },
+                FnSignature { params: vec![], ret: None },
+            ]
+        );
+        let strukt = file.tree.iter().find(|node| node.node_type == "STRUCT").unwrap();
+        assert_eq!(file.signature(strukt), None);
+    }
+
+    #[test]
+    fn impls_are_captured() {
+        let content = "impl<T> Clone for Foo<T> {}\nimpl Foo<u8> {}\nimpl !Send for Bar {}";
+        let file = parse(content, &ParseOptions { capture_impls: true, ..Default::default() });
+        let impls: Vec<_> = file
+            .tree
+            .iter()
+            .filter(|node| node.node_type == "IMPL")
+            .map(|node| file.impl_info(node).unwrap())
+            .collect();
+        let info = |trait_: Option<&str>, target: &str| ImplInfo {
+            trait_: trait_.map(str::to_owned),
+            target: target.to_owned(),
+        };
+        assert_eq!(
+            impls,
+            [info(Some("Clone"), "Foo<T>"), info(None, "Foo<u8>"), info(Some("!Send"), "Bar")]
+        );
+    }
+
+    #[test]
+    fn trait_items_are_captured() {
+        let options = ParseOptions { capture_trait_items: true, ..Default::default() };
+        let content = "trait T {\n    /// Docs\n    #[must_use]\n    fn a(&self);\n    \
+                       fn b() -> i32 { 0 }\n    type Item: Clone = u8;\n    type Out;\n    \
+                       const N: usize;\n    m!();\n}\n";
+        let file = parse(content, &options);
+        let trait_ = file.tree.iter().find(|node| node.node_type == "TRAIT").unwrap();
+        let item = |kind, signature: &str, has_default| TraitItem {
+            kind,
+            signature: signature.to_owned(),
+            has_default,
+        };
+        assert_eq!(
+            file.trait_items(trait_),
+            [
+                item(TraitItemKind::Fn, "fn a(&self)", false),
+                item(TraitItemKind::Fn, "fn b() -> i32", true),
+                item(TraitItemKind::Type, "type Item: Clone", true),
+                item(TraitItemKind::Type, "type Out", false),
+                item(TraitItemKind::Const, "const N: usize", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_are_captured() {
+        let options = ParseOptions { capture_fields: true, ..Default::default() };
+        let content = "struct S { a: i32, b: String }\nenum E { A, B(i32) }\n\
+                       struct T(u8, Vec<fn(x: u8)>);\nstruct U;\n";
+        let file = parse(content, &options);
+        let items: Vec<_> = file
+            .tree
+            .iter()
+            .filter(|node| matches!(node.node_type.as_str(), "STRUCT" | "ENUM"))
+            .collect();
+        assert_eq!(file.struct_fields(items[0]), [("a", "i32"), ("b", "String")]);
+        assert_eq!(file.enum_variants(items[1]), ["A", "B"]);
+        assert_eq!(file.struct_fields(items[2]), [("0", "u8"), ("1", "Vec<fn(x: u8)>")]);
+        assert_eq!(file.struct_fields(items[3]), []);
+        assert_eq!(file.enum_variants(items[0]), Vec::<&str>::new());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn imports_are_captured() {
+        let content = r#"
+use std::collections::{HashMap, hash_map::{self, Entry as E}};
+use crate::foo::*;
+use ::core::fmt::Debug as _;
+mod inner { use super::bar; }
+"#;
+        let file = parse(content, &ParseOptions { capture_imports: true, ..Default::default() });
+        assert_eq!(
+            file.imports(),
+            [
+                "std::collections::HashMap",
+                "std::collections::hash_map",
+                "std::collections::hash_map::Entry as E",
+                "crate::foo::*",
+                "::core::fmt::Debug as _",
+                "super::bar",
+            ]
+        );
+    }
+
+    #[test]
+    fn module_doc_is_captured() {
+        let options = ParseOptions { capture_module_doc: true, ..Default::default() };
+        let content = "#!/usr/bin/env rust-script\n//! Crate docs\n//!\n//!  indented\n\
+                       #![allow(unused)]\n// not docs\n/*! More */\nfn f() {}\n//! too late\n";
+        let file = parse(content, &options);
+        assert_eq!(file.module_doc(), Some("Crate docs\n\n indented\nMore"));
+        assert_eq!(parse("/// Item docs\nfn f() {}", &options).module_doc(), None);
+        assert_eq!(parse("", &options).module_doc(), None);
+    }
+
+    #[test]
+    fn initializer_sizes_are_captured() {
+        let options = ParseOptions { capture_initializer_sizes: true, ..Default::default() };
+        let content = "const TABLE: [u32; 4] = [1, 2, 3, 4 + 5];\nconst SMALL: u8 = 1;\n\
+                       static S: &str = \"s\";\ntrait T { const N: usize; }\n";
+        let file = parse(content, &options);
+        let sizes: Vec<_> = file
+            .tree
+            .iter()
+            .filter(|node| matches!(node.node_type.as_str(), "CONST" | "STATIC"))
+            .map(|item| file.initializer_nodes(item))
+            .collect();
+        assert_eq!(sizes[1..], [Some(1), Some(1), None]);
+        assert!(sizes[0] > sizes[1]);
+    }
+
+    #[test]
+    fn derives_are_captured() {
+        let options = ParseOptions { capture_derives: true, ..Default::default() };
+        let file = parse(
+            r#"
+#[derive(Debug, Clone)]
+struct S;
+#[cfg_attr(feature = "serde", derive(serde::Serialize), cfg_attr(test, derive(Default)))]
+#[derive(PartialEq)]
+enum E {}
+"#,
+            &options,
+        );
+        let adts: Vec<_> =
+            file.tree.iter().filter(|node| matches!(&*node.node_type, "STRUCT" | "ENUM")).collect();
+        assert_eq!(file.derives(adts[0]), ["Debug", "Clone"]);
+        assert_eq!(file.derives(adts[1]), ["serde::Serialize", "Default", "PartialEq"]);
+    }
+}
@@ -1,30 +1,64 @@
-/// Project Asset Dumper
-///
-/// This binary recursively scans a directory for Rust source files (`.rs`), parses each file,
-/// collects their ASTs and parse errors, and encodes all files into a single binary asset file.
-///
-/// # Usage
-///
-///     cargo run --bin project_dump -- <path-to-directory> [--out <output-path>]
-///
-/// - `<path-to-directory>`: The root directory to scan for Rust files.
-/// - `--out <output-path>`: (Optional) Path to write the output asset file. Defaults to `project.asset`.
-///
-/// # Example
-///
-///     cargo run --bin project_dump -- ./my_rust_project --out my_project.asset
-///
-/// This will create `my_project.asset` containing all `.rs` files in `./my_rust_project` and subdirectories.
+//! Project Asset Dumper
+//!
+//! This binary recursively scans a directory for Rust source files (`.rs`), parses each file,
+//! collects their ASTs and parse errors, and encodes all files into a single binary asset file.
+//!
+//! # Usage
+//!
+//!     cargo run --bin project_dump -- <path-to-directory> [--out <output-path>] [options]
+//!
+//! - `<path-to-directory>`: The root directory to scan for Rust files.
+//! - `--out <output-path>`: (Optional) Path to write the output asset file. Defaults to `project.asset`.
+//! - `--capture-literals`: (Optional) Also store the unescaped values of string and char literals.
+//! - `--subtree-hashes`: (Optional) Store a structural hash for every node.
+//! - `--capture-derives`: (Optional) Store the derive macros applied to each struct, enum and union.
+//! - `--capture-generics`: (Optional) Store the generic parameter names of each item.
+//! - `--capture-visibility`: (Optional) Store the declared visibility of each item.
+//! - `--capture-signatures`: (Optional) Store the parameters and return type of each function.
+//! - `--capture-impls`: (Optional) Store the trait and self type of each impl block.
+//! - `--capture-imports`: (Optional) Store the paths imported by each `use` item, with groups expanded.
+//! - `--capture-fields`: (Optional) Store the fields of each struct and the variants of each enum.
+//! - `--capture-trait-items`: (Optional) Store the functions, types and consts declared in each trait.
+//! - `--capture-module-doc`: (Optional) Store the `//!` doc comments at the top of each file.
+//! - `--capture-initializer-sizes`: (Optional) Store the node count of each `const` and `static` initializer.
+//! - `--capture-macro-calls`: (Optional) Store the name and argument range of each macro call.
+//! - `--flag-unsafe`: (Optional) Add a note annotation on every unsafe function, block, impl and trait.
+//! - `--flag-unresolved-macros`: (Optional) Add a warning on every macro call the parser had to recover from.
+//! - `--outline`: (Optional) Only store the items of each file and their names instead of the full tree.
+//!   The files are still parsed in full, but the `--capture-*` and `--flag-*` options are ignored.
+//! - `--capture-tests`: (Optional) Store the name and range of each `#[test]`-like function.
+//! - `--capture-line-spans`: (Optional) Store the number of lines each item spans.
+//! - `--capture-line-offsets`: (Optional) Store the offset at which each line starts.
+//! - `--capture-trivia`: (Optional) Tag every comment and whitespace as leading, trailing or standalone.
+//! - `--anonymize`: (Optional) Drop the file contents and replace identifiers and literals by pseudonyms.
+//! - `--strip-bom`: (Optional) Remove a leading UTF-8 byte order mark from each file before parsing.
+//! - `--error-context <bytes>`: (Optional) Store up to `<bytes>` bytes of the source text at each parse error.
+//! - `--edition <year>`: (Optional) The Rust edition to parse the files under. Defaults to the latest.
+//! - `--max-files <count>`: (Optional) Stop after finding this many `.rs` files, with a warning.
+//! - `--skip-unparseable`: (Optional) Leave out files that are mostly `ERROR` nodes, such as shell
+//!   scripts with a `.rs` extension.
+//! - `--unparseable-threshold <ratio>`: (Optional) The error node ratio above which
+//!   `--skip-unparseable` leaves out a file. Defaults to 0.3.
+//!
+//! # Example
+//!
+//!     cargo run --bin project_dump -- ./my_rust_project --out my_project.asset
+//!
+//! This will create `my_project.asset` containing all `.rs` files in `./my_rust_project` and subdirectories.
 
-use std::{env, fs, process::exit, path::Path, io::BufWriter};
-use walkdir::WalkDir;
-use rust_analyzer::asset::Project;
-use rust_analyzer::asset_gen::parse_rust_to_asset_file;
+use rust_analyzer::asset_gen::{
+    DEFAULT_UNPARSEABLE_THRESHOLD, DumpEvent, DumpOptions, ParseOptions, dump_directory,
+};
+use std::{env, path::Path, process::exit};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut dir = None;
     let mut out_path = String::from("project.asset");
+    let mut options = ParseOptions::default();
+    let mut skip_unparseable = false;
+    let mut unparseable_threshold = DEFAULT_UNPARSEABLE_THRESHOLD;
+    let mut max_files = None;
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
@@ -36,11 +70,74 @@ fn main() {
                 }
                 out_path = args[i].clone();
             }
+            "--capture-literals" => options.capture_literals = true,
+            "--subtree-hashes" => options.subtree_hashes = true,
+            "--capture-derives" => options.capture_derives = true,
+            "--capture-macro-calls" => options.capture_macro_calls = true,
+            "--capture-generics" => options.capture_generics = true,
+            "--capture-visibility" => options.capture_visibility = true,
+            "--capture-signatures" => options.capture_signatures = true,
+            "--capture-impls" => options.capture_impls = true,
+            "--capture-imports" => options.capture_imports = true,
+            "--capture-fields" => options.capture_fields = true,
+            "--capture-trait-items" => options.capture_trait_items = true,
+            "--capture-module-doc" => options.capture_module_doc = true,
+            "--capture-initializer-sizes" => options.capture_initializer_sizes = true,
+            "--flag-unsafe" => options.flag_unsafe = true,
+            "--flag-unresolved-macros" => options.flag_unresolved_macros = true,
+            "--outline" => options.outline = true,
+            "--anonymize" => options.anonymize = true,
+            "--capture-trivia" => options.capture_trivia = true,
+            "--capture-tests" => options.capture_tests = true,
+            "--capture-line-spans" => options.capture_line_spans = true,
+            "--capture-line-offsets" => options.capture_line_offsets = true,
+            "--strip-bom" => options.strip_bom = true,
+            "--edition" => {
+                i += 1;
+                match args.get(i).and_then(|arg| arg.parse().ok()) {
+                    Some(edition) => options.edition = Some(edition),
+                    None => {
+                        eprintln!("Expected an edition (2015, 2018, 2021 or 2024) for --edition");
+                        exit(1);
+                    }
+                }
+            }
+            "--error-context" => {
+                i += 1;
+                match args.get(i).and_then(|arg| arg.parse().ok()) {
+                    Some(max_len) => options.error_context = Some(max_len),
+                    None => {
+                        eprintln!("Expected a number of bytes for --error-context");
+                        exit(1);
+                    }
+                }
+            }
+            "--max-files" => {
+                i += 1;
+                match args.get(i).and_then(|arg| arg.parse().ok()) {
+                    Some(count) => max_files = Some(count),
+                    None => {
+                        eprintln!("Expected a number of files for --max-files");
+                        exit(1);
+                    }
+                }
+            }
+            "--skip-unparseable" => skip_unparseable = true,
+            "--unparseable-threshold" => {
+                i += 1;
+                match args.get(i).and_then(|arg| arg.parse().ok()) {
+                    Some(threshold) => unparseable_threshold = threshold,
+                    None => {
+                        eprintln!("Expected a ratio for --unparseable-threshold");
+                        exit(1);
+                    }
+                }
+            }
             _ if dir.is_none() => {
                 dir = Some(args[i].clone());
             }
             _ => {
-                eprintln!("Usage: {} <path-to-directory> [--out <output-path>]", args[0]);
+                eprintln!("Usage: {} <path-to-directory> [--out <output-path>] [options]", args[0]);
                 exit(1);
             }
         }
@@ -49,40 +146,34 @@ fn main() {
     let dir = match dir {
         Some(d) => d,
         None => {
-            eprintln!("Usage: {} <path-to-directory> [--out <output-path>]", args[0]);
+            eprintln!("Usage: {} <path-to-directory> [--out <output-path>] [options]", args[0]);
             exit(1);
         }
     };
-    let mut files = Vec::new();
-    for entry in WalkDir::new(&dir).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        if path.is_file() && path.extension().map_or(false, |ext| ext == "rs") {
-            let path_str = path.display().to_string();
-            let text = match fs::read_to_string(path) {
-                Ok(t) => t,
-                Err(e) => {
-                    eprintln!("Failed to read file {}: {}", path_str, e);
-                    continue;
-                }
-            };
-            eprintln!("Parsing file: {}", path_str);
-            let file_asset = parse_rust_to_asset_file(path_str, text);
-            files.push(file_asset);
+    let options = DumpOptions {
+        parse: options,
+        skip_unparseable: skip_unparseable.then_some(unparseable_threshold),
+        out: Some(out_path.clone().into()),
+        max_files,
+        ..Default::default()
+    };
+    let result = dump_directory(Path::new(&dir), &options, &mut |event| match event {
+        DumpEvent::FileParsed { path, nodes, errors } => {
+            eprintln!("Parsed file: {} ({} nodes, {} errors)", path, nodes, errors);
         }
-    }
-    let project = Project { files };
-    let out_path = Path::new(&out_path);
-    let out_file = match fs::File::create(out_path) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("Failed to create output file {}: {}", out_path.display(), e);
-            exit(1);
+        DumpEvent::FileSkipped { path, reason } => eprintln!("Skipping file {}: {}", path, reason),
+        DumpEvent::LimitReached { max_files } => {
+            eprintln!(
+                "Warning: stopped after {} files, raise --max-files to include more",
+                max_files
+            )
         }
-    };
-    let mut writer = BufWriter::new(out_file);
-    if let Err(e) = project.encode(&mut writer) {
-        eprintln!("Failed to encode project asset: {}", e);
+        DumpEvent::EncodeStarted => eprintln!("Encoding project asset"),
+        DumpEvent::Done => {}
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to dump project: {}", e);
         exit(1);
     }
-    println!("Project asset written to {}", out_path.display());
-} 
\ No newline at end of file
+    println!("Project asset written to {}", out_path);
+}
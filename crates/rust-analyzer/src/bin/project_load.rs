@@ -0,0 +1,283 @@
+//! Project Asset Loader
+//!
+//! This binary decodes an asset file produced by `project_dump`, optionally transforms it, and
+//! either writes the result to a new asset file or prints a summary of the files it contains.
+//!
+//! # Usage
+//!
+//!     cargo run --bin project_load -- <asset-path> [--rebase <from>=<to>] [--out <output-path>] [options]
+//!
+//! - `<asset-path>`: The asset file to load, in any format version `project_load` can read.
+//! - `--rebase <from>=<to>`: (Optional) Rewrite file paths starting with `<from>` to start with `<to>`.
+//! - `--out <output-path>`: (Optional) Write the loaded project to a new asset file instead of
+//!   printing a summary.
+//! - `--reencode`: (Optional) Upgrade the asset to the current format version and the selected
+//!   encode options, then check that the written asset decodes back to the same project, verifying
+//!   its checksums. An asset that is already encoded that way is skipped with a note and nothing is
+//!   written, so that a whole directory of assets can be upgraded in one go. Requires `--out`.
+//! - `--list-errors`: (Optional) Print every annotation as a `path:line:col: severity: message` line
+//!   instead of the summary.
+//! - `--error-histogram`: (Optional) Print how often each error message occurs, most common first,
+//!   instead of the summary. Warnings and notes are not counted.
+//! - `--normalize-positions`: (Optional) With `--error-histogram`, count messages that only differ
+//!   in the numbers they contain, such as positions, together.
+//! - `--sections`: (Optional) Print how many bytes each section of the encoding takes instead of
+//!   the summary, for the output format selected by `--delta-offsets`.
+//! - `--identify`: (Optional) Only print the version and layout of the asset, read from its
+//!   header, without decoding it.
+//! - `--delta-offsets`: (Optional) Store node ranges in the output as compact deltas from their
+//!   parents.
+//!
+//! # Examples
+//!
+//!     cargo run --bin project_load -- my_project.asset --rebase /home/me/proj=/work/proj --out moved.asset
+//!
+//! This will create `moved.asset` with every path under `/home/me/proj` moved to `/work/proj`.
+//!
+//!     cargo run --bin project_load -- --reencode --out new.asset old.asset
+//!
+//! This will rewrite `old.asset`, which may use any older format version, as `new.asset` in the
+//! current version.
+
+use rust_analyzer::asset::{CURRENT_ASSET_VERSION, DecodeOptions, EncodeOptions, Project};
+use std::{
+    env, fs, io::BufReader, io::BufWriter, io::Cursor, io::Write, path::Path, process::exit,
+};
+
+fn usage(bin: &str) -> ! {
+    eprintln!("Usage: {} <asset-path> [--rebase <from>=<to>] [--out <output-path>] [options]", bin);
+    exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut asset_path = None;
+    let mut out_path = None;
+    let mut rebase = None;
+    let mut reencode = false;
+    let mut list_errors = false;
+    let mut error_histogram = false;
+    let mut normalize_positions = false;
+    let mut sections = false;
+    let mut identify = false;
+    let decode_options = DecodeOptions::default();
+    let mut encode_options = EncodeOptions::default();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--out" => {
+                i += 1;
+                if i >= args.len() {
+                    eprintln!("Missing value for --out");
+                    exit(1);
+                }
+                out_path = Some(args[i].clone());
+            }
+            "--rebase" => {
+                i += 1;
+                let Some((from, to)) = args.get(i).and_then(|arg| arg.split_once('=')) else {
+                    eprintln!("Expected --rebase <from>=<to>");
+                    exit(1);
+                };
+                rebase = Some((from.to_owned(), to.to_owned()));
+            }
+            "--reencode" => reencode = true,
+            "--list-errors" => list_errors = true,
+            "--error-histogram" => error_histogram = true,
+            "--normalize-positions" => normalize_positions = true,
+            "--sections" => sections = true,
+            "--identify" => identify = true,
+            "--delta-offsets" => encode_options.delta_offsets = true,
+            _ if asset_path.is_none() => {
+                asset_path = Some(args[i].clone());
+            }
+            _ => usage(&args[0]),
+        }
+        i += 1;
+    }
+    let Some(asset_path) = asset_path else { usage(&args[0]) };
+    if reencode && out_path.is_none() {
+        eprintln!("--reencode requires --out <output-path>");
+        exit(1);
+    }
+    if normalize_positions && !error_histogram {
+        eprintln!("--normalize-positions requires --error-histogram");
+        exit(1);
+    }
+    let asset_file = match fs::File::open(&asset_path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Failed to open asset file {}: {}", asset_path, e);
+            exit(1);
+        }
+    };
+    if identify {
+        let info = match Project::identify(BufReader::new(asset_file)) {
+            Ok(info) => info,
+            Err(e) => {
+                eprintln!("Failed to read asset file {}: {}", asset_path, e);
+                exit(1);
+            }
+        };
+        if !info.valid_magic {
+            println!("{}: not a project asset, {} bytes", asset_path, info.size);
+        } else if !info.supported {
+            println!("{}: project asset, unsupported version {}", asset_path, info.version);
+        } else {
+            println!(
+                "{}: project asset version {}, {} files, {} offsets, {}, {} bytes",
+                asset_path,
+                info.version,
+                info.num_files,
+                match (info.num_files, info.delta_offsets) {
+                    (0, _) => "no",
+                    (_, true) => "delta",
+                    (_, false) => "absolute",
+                },
+                if info.has_index { "indexed" } else { "no index" },
+                info.size
+            );
+        }
+        return;
+    }
+    let mut project =
+        match Project::decode_with_options(BufReader::new(asset_file), &decode_options) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Failed to decode project asset {}: {}", asset_path, e);
+                exit(1);
+            }
+        };
+    if let Some((from, to)) = &rebase {
+        let rebased = project.rebase_paths(from, to);
+        eprintln!("Rebased {} of {} paths", rebased, project.files.len());
+    }
+    if list_errors {
+        let stdout = std::io::stdout();
+        if let Err(e) = project.write_annotations_plain(stdout.lock()) {
+            eprintln!("Failed to list annotations: {}", e);
+            exit(1);
+        }
+    }
+    if error_histogram {
+        let histogram = if normalize_positions {
+            project.normalized_error_message_histogram()
+        } else {
+            project.error_message_histogram()
+        };
+        let mut counts: Vec<_> = histogram.into_iter().collect();
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (message, count) in counts {
+            println!("{:>8} {}", count, message);
+        }
+    }
+    if sections {
+        let sizes = project.section_sizes(&encode_options);
+        let total = sizes.total();
+        for (name, size) in [
+            ("header", sizes.header),
+            ("files", sizes.files),
+            ("nodes", sizes.nodes),
+            ("errors", sizes.errors),
+            ("literals", sizes.literals),
+            ("metadata", sizes.metadata),
+            ("line offsets", sizes.line_offsets),
+            ("string table", sizes.string_table),
+            ("index", sizes.index),
+        ] {
+            let percent = if total == 0 { 0.0 } else { size as f64 * 100.0 / total as f64 };
+            println!("{:>12}: {} bytes ({:.1}%)", name, size, percent);
+        }
+        println!("{:>12}: {} bytes", "total", total);
+    }
+    let Some(out_path) = out_path else {
+        if list_errors || error_histogram || sections {
+            return;
+        }
+        for file in &project.files {
+            println!("{}: {} nodes, {} errors", file.path, file.tree.len(), file.errors.len());
+        }
+        return;
+    };
+    let out_path = Path::new(&out_path);
+    if reencode {
+        reencode_to(&project, &asset_path, out_path, &encode_options);
+        return;
+    }
+    let out_file = match fs::File::create(out_path) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Failed to create output file {}: {}", out_path.display(), e);
+            exit(1);
+        }
+    };
+    let mut writer = BufWriter::new(out_file);
+    if let Err(e) = project.encode_with_options(&mut writer, &encode_options) {
+        eprintln!("Failed to encode project asset: {}", e);
+        exit(1);
+    }
+    if let Err(e) = writer.flush() {
+        eprintln!("Failed to write output file {}: {}", out_path.display(), e);
+        exit(1);
+    }
+    println!("Project asset written to {}", out_path.display());
+}
+
+/// Writes `project`, decoded from `asset_path`, to `out_path` for `--reencode` and checks that the
+/// written asset decodes back to it.
+fn reencode_to(
+    project: &Project,
+    asset_path: &str,
+    out_path: &Path,
+    encode_options: &EncodeOptions,
+) {
+    let input = match fs::read(asset_path) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("Failed to read asset file {}: {}", asset_path, e);
+            exit(1);
+        }
+    };
+    let encoded = match project.encode_to_vec(encode_options) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            eprintln!("Failed to encode project asset: {}", e);
+            exit(1);
+        }
+    };
+    if encoded == input {
+        println!("Skipped {}, already current", asset_path);
+        return;
+    }
+    if let Err(e) = fs::write(out_path, &encoded) {
+        eprintln!("Failed to write output file {}: {}", out_path.display(), e);
+        exit(1);
+    }
+    // Decoding through the index verifies the checksum of every record.
+    let written = fs::File::open(out_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Project::decode_where(BufReader::new(file), |_| true));
+    match written {
+        Ok(written) if written == *project => {}
+        Ok(_) => {
+            eprintln!(
+                "Re-encoded asset {} does not decode to the same project",
+                out_path.display()
+            );
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to verify re-encoded asset {}: {}", out_path.display(), e);
+            exit(1);
+        }
+    }
+    let from = Project::identify(Cursor::new(&input)).map(|info| info.version).unwrap_or_default();
+    println!(
+        "Re-encoded {} files from {} (version {}) to {} (version {}), verified",
+        project.files.len(),
+        asset_path,
+        from,
+        out_path.display(),
+        CURRENT_ASSET_VERSION
+    );
+}
@@ -0,0 +1,54 @@
+//! Runs the `project_load` binary on checked-in assets.
+
+use std::{fs, io::Cursor, path::PathBuf, process::Output};
+
+use rust_analyzer::asset::{CURRENT_ASSET_VERSION, Project};
+use rustc_hash::FxHashMap;
+
+/// A version 1 asset with a single file, `src/main.rs`, holding one node and one error.
+const V1_ASSET: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/v1.asset");
+
+fn project_load(args: &[&str]) -> Output {
+    let mut cmd = toolchain::command(
+        env!("CARGO_BIN_EXE_project_load"),
+        env!("CARGO_MANIFEST_DIR"),
+        &FxHashMap::default(),
+    );
+    cmd.args(args).output().unwrap()
+}
+
+fn out_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("project_load_{}_{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn reencode_v1_asset() {
+    let dir = out_dir("reencode");
+    let new = dir.join("new.asset");
+    let output = project_load(&[V1_ASSET, "--reencode", "--out", new.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Re-encoded 1 files from "), "{stdout}");
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+
+    let reencoded = fs::read(&new).unwrap();
+    let info = Project::identify(Cursor::new(&reencoded)).unwrap();
+    assert_eq!(info.version, CURRENT_ASSET_VERSION);
+    assert!(info.has_index);
+    let original = Project::decode(Cursor::new(fs::read(V1_ASSET).unwrap())).unwrap();
+    assert_eq!(Project::decode_where(Cursor::new(&reencoded), |_| true).unwrap(), original);
+    assert_eq!(original.files[0].path, "src/main.rs");
+
+    // An asset that is already current is skipped without failing or writing anything.
+    let again = dir.join("again.asset");
+    let output =
+        project_load(&[new.to_str().unwrap(), "--reencode", "--out", again.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("already current"), "{stdout}");
+    assert!(!again.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}